@@ -0,0 +1,125 @@
+use std::env;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::assembler::parser::parse_asm_file;
+use crate::assembler::symbol_table::create_symbol_table;
+
+/// Tells apart the files of programs assembled at the same time
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// The Hack CPU, for running what the assembler writes in tests
+pub struct Cpu {
+    pub ram: Vec<i16>,
+    rom: Vec<u16>,
+    pub pc: usize,
+    a: i16,
+    d: i16,
+    pub steps: u64,
+    pub halted: bool,
+}
+
+impl Cpu {
+    /// Loads the `0`s and `1`s the assembler writes, one instruction per line
+    pub fn new(binary: &str) -> Self {
+        let rom = binary
+            .lines()
+            .map(|line| u16::from_str_radix(line, 2).expect("The assembler writes binary"))
+            .collect();
+        Cpu {
+            ram: vec![0; 32768],
+            rom,
+            pc: 0,
+            a: 0,
+            d: 0,
+            steps: 0,
+            halted: false,
+        }
+    }
+
+    /// Assembles the program and loads it
+    pub fn from_asm(asm: &str) -> Self {
+        let path = env::temp_dir().join(format!(
+            "n2ttools_cpu_{}_{}.asm",
+            std::process::id(),
+            NEXT_FILE.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, asm).unwrap();
+        let binary = parse_asm_file(&path, create_symbol_table(), false);
+        fs::remove_file(&path).unwrap();
+        Cpu::new(&binary)
+    }
+
+    /// Runs until the program spins in an `(END) @END 0;JMP` loop or runs out of steps
+    pub fn run(&mut self, max_steps: u64) {
+        while !self.halted && self.steps < max_steps {
+            self.step();
+        }
+    }
+
+    pub fn step(&mut self) {
+        let Some(&instruction) = self.rom.get(self.pc) else {
+            self.halted = true;
+            return;
+        };
+        self.steps += 1;
+
+        if instruction & 0x8000 == 0 {
+            self.a = instruction as i16;
+            self.pc += 1;
+            return;
+        }
+
+        let address = self.a as u16 as usize;
+        let x = self.d;
+        let y = match instruction & 0x1000 {
+            0 => self.a,
+            _ => self.ram[address],
+        };
+        let bit = |number: u16| instruction & (1 << number) != 0;
+        let x = if bit(11) { 0 } else { x };
+        let x = if bit(10) { !x } else { x };
+        let y = if bit(9) { 0 } else { y };
+        let y = if bit(8) { !y } else { y };
+        let out = if bit(7) { x.wrapping_add(y) } else { x & y };
+        let out = if bit(6) { !out } else { out };
+
+        if bit(3) {
+            self.ram[address] = out;
+        }
+        if bit(5) {
+            self.a = out;
+        }
+        if bit(4) {
+            self.d = out;
+        }
+
+        let jump = (bit(2) && out < 0) || (bit(1) && out == 0) || (bit(0) && out > 0);
+        if jump {
+            let target = address;
+            // `@END` right before a jump back to it
+            if target + 1 == self.pc {
+                self.halted = true;
+            }
+            self.pc = target;
+        } else {
+            self.pc += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu() {
+        // RAM[2] = RAM[0] + RAM[1]
+        let mut cpu = Cpu::from_asm("@0\nD=M\n@1\nD=D+M\n@2\nM=D\n(END)\n@END\n0;JMP\n");
+        cpu.ram[0] = -7;
+        cpu.ram[1] = 3;
+        cpu.run(100);
+        assert!(cpu.halted);
+        assert_eq!(cpu.ram[2], -4);
+    }
+}
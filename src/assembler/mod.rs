@@ -1,3 +1,5 @@
+#[cfg(test)]
+pub mod cpu;
 pub mod parser;
 pub mod run;
 pub mod symbol_table;
@@ -1,11 +1,13 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 
 use crate::assembler::parser::parse_asm_file;
 use crate::assembler::symbol_table::create_symbol_table;
+use crate::vm::run::source_map_path;
+use crate::vm::source_map::{SourceEntry, add_rom_addresses};
 
-pub fn run_assembler<P>(file: P, debug: bool)
+pub fn run_assembler<P>(file: P, debug: bool, source_map: bool)
 where
     P: AsRef<Path> + std::fmt::Debug,
 {
@@ -22,8 +24,32 @@ where
         println!("Output is:\n{}", output);
     }
 
-    let mut file = File::create(output_path).unwrap();
-    file.write_all(output.as_bytes()).unwrap();
+    let mut output_file = File::create(&output_path).unwrap();
+    output_file.write_all(output.as_bytes()).unwrap();
+
+    if source_map {
+        write_source_map(file.as_ref(), &output_path);
+    }
+}
+
+/// Reads the source map written next to the .asm file and saves a copy with ROM addresses
+fn write_source_map(asm_path: &Path, output_path: &Path) {
+    let input_map = source_map_path(asm_path);
+    let Ok(contents) = fs::read_to_string(&input_map) else {
+        println!("Could not read the source map {:?}", input_map);
+        return;
+    };
+    let Ok(mut entries) = serde_json::from_str::<Vec<SourceEntry>>(&contents) else {
+        println!("{:?} isn't a valid source map", input_map);
+        return;
+    };
+
+    let asm = fs::read_to_string(asm_path).unwrap();
+    add_rom_addresses(&mut entries, &asm);
+
+    let output_map = source_map_path(output_path);
+    fs::write(&output_map, serde_json::to_string_pretty(&entries).unwrap()).unwrap();
+    println!("Saving source map to {:?}", output_map);
 }
 
 fn check_filetype<P>(file: &P) -> bool
@@ -62,8 +88,6 @@ where
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-
     // #[test]
     // fn run_assm_add() {
     //     run_assembler("../nand2tetris/nand2tetris/projects/6/add/Add.asm");
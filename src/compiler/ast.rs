@@ -0,0 +1,304 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::compiler::diagnostics::Span;
+
+/// A name written in the source, with where it was written
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Identifier {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum Type {
+    Int,
+    Char,
+    Boolean,
+    /// Only used as a subroutine's return type
+    Void,
+    Class(String),
+}
+
+impl Type {
+    /// The type for a name as it's stored in the symbol table
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "int" => Type::Int,
+            "char" => Type::Char,
+            "boolean" => Type::Boolean,
+            "void" => Type::Void,
+            _ => Type::Class(name.to_string()),
+        }
+    }
+
+    /// How the type reads in an error message, like "an int" or "a String"
+    pub fn description(&self) -> String {
+        let name = self.to_string();
+        match name.chars().next() {
+            Some('a' | 'e' | 'i' | 'o' | 'u' | 'A' | 'E' | 'I' | 'O' | 'U') => {
+                format!("an {}", name)
+            }
+            _ => format!("a {}", name),
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Char => write!(f, "char"),
+            Type::Boolean => write!(f, "boolean"),
+            Type::Void => write!(f, "void"),
+            Type::Class(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Class {
+    pub name: Identifier,
+    pub variables: Vec<ClassVarDec>,
+    pub subroutines: Vec<Subroutine>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ClassVarKind {
+    Static,
+    Field,
+}
+
+impl Display for ClassVarKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClassVarKind::Static => write!(f, "static"),
+            ClassVarKind::Field => write!(f, "field"),
+        }
+    }
+}
+
+/// `static int a, b;` or `field int a, b;`
+#[derive(Debug, Clone, Serialize)]
+pub struct ClassVarDec {
+    pub kind: ClassVarKind,
+    pub var_type: Type,
+    pub names: Vec<Identifier>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SubroutineKind {
+    Constructor,
+    Function,
+    Method,
+}
+
+impl Display for SubroutineKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubroutineKind::Constructor => write!(f, "constructor"),
+            SubroutineKind::Function => write!(f, "function"),
+            SubroutineKind::Method => write!(f, "method"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Subroutine {
+    pub kind: SubroutineKind,
+    pub return_type: Type,
+    pub name: Identifier,
+    pub parameters: Vec<Parameter>,
+    pub locals: Vec<VarDec>,
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Parameter {
+    pub var_type: Type,
+    pub name: Identifier,
+}
+
+/// `var int a, b;`
+#[derive(Debug, Clone, Serialize)]
+pub struct VarDec {
+    pub var_type: Type,
+    pub names: Vec<Identifier>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum Statement {
+    Let(LetStatement),
+    If(IfStatement),
+    While(WhileStatement),
+    Do(DoStatement),
+    Return(ReturnStatement),
+}
+
+impl Statement {
+    /// Where the statement's keyword is
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Let(statement) => statement.span,
+            Statement::If(statement) => statement.span,
+            Statement::While(statement) => statement.span,
+            Statement::Do(statement) => statement.span,
+            Statement::Return(statement) => statement.span,
+        }
+    }
+}
+
+/// `let name = value;` or `let name[index] = value;`
+#[derive(Debug, Clone, Serialize)]
+pub struct LetStatement {
+    pub name: Identifier,
+    pub index: Option<Expression>,
+    pub value: Expression,
+    /// Where the `let` keyword is
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IfStatement {
+    pub condition: Expression,
+    pub then_statements: Vec<Statement>,
+    pub else_statements: Option<Vec<Statement>>,
+    /// Where the `if` keyword is
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WhileStatement {
+    pub condition: Expression,
+    pub statements: Vec<Statement>,
+    /// Where the `while` keyword is
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DoStatement {
+    pub call: SubroutineCall,
+    /// Where the `do` keyword is
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReturnStatement {
+    pub value: Option<Expression>,
+    /// Where the `return` keyword is
+    pub span: Span,
+}
+
+/// Jack has no operator precedence, so an expression is its terms and operators in order
+#[derive(Debug, Clone, Serialize)]
+pub struct Expression {
+    pub term: Term,
+    pub operations: Vec<(Op, Term)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Or,
+    Lt,
+    Gt,
+    Eq,
+}
+
+impl Op {
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "+" => Some(Op::Add),
+            "-" => Some(Op::Sub),
+            "*" => Some(Op::Mul),
+            "/" => Some(Op::Div),
+            "&amp;" => Some(Op::And),
+            "|" => Some(Op::Or),
+            "&lt;" => Some(Op::Lt),
+            "&gt;" => Some(Op::Gt),
+            "=" => Some(Op::Eq),
+            _ => None,
+        }
+    }
+
+    /// The symbol as it's written in xml output
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::And => "&amp;",
+            Op::Or => "|",
+            Op::Lt => "&lt;",
+            Op::Gt => "&gt;",
+            Op::Eq => "=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+impl UnaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "~",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum KeywordConstant {
+    True,
+    False,
+    Null,
+    This,
+}
+
+impl Display for KeywordConstant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeywordConstant::True => write!(f, "true"),
+            KeywordConstant::False => write!(f, "false"),
+            KeywordConstant::Null => write!(f, "null"),
+            KeywordConstant::This => write!(f, "this"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Term {
+    pub kind: TermKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum TermKind {
+    /// Kept as written, so the xml output matches the source
+    IntegerConstant(String),
+    StringConstant(String),
+    Keyword(KeywordConstant),
+    Variable(Identifier),
+    ArrayAccess(Identifier, Box<Expression>),
+    Call(SubroutineCall),
+    Parenthesized(Box<Expression>),
+    Unary(UnaryOp, Box<Term>),
+}
+
+/// `name(arguments)` or `receiver.name(arguments)`, where the receiver is a class or a variable
+#[derive(Debug, Clone, Serialize)]
+pub struct SubroutineCall {
+    pub receiver: Option<Identifier>,
+    pub name: Identifier,
+    pub arguments: Vec<Expression>,
+}
@@ -1,30 +1,291 @@
+use crate::compiler::ast::{
+    Class, Expression, Identifier, IfStatement, KeywordConstant, LetStatement, Op, Statement,
+    Subroutine, SubroutineCall, SubroutineKind, Term, TermKind, UnaryOp, WhileStatement,
+};
+use crate::compiler::diagnostics::{Diagnostic, Span};
 use crate::compiler::parser::Compiler;
+use crate::compiler::registry::CallTarget;
 
 use super::symbol_table::Symbol;
 
+/// The biggest number `push constant` takes, the vm's constants are 15 bits
+const MAX_CONSTANT: u16 = 32767;
+
+/// The number in an integer constant term, if it fits in 16 bits
+fn constant_value(term: &Term) -> Option<u16> {
+    match &term.kind {
+        TermKind::IntegerConstant(constant) => constant.parse().ok(),
+        _ => None,
+    }
+}
+
 impl Compiler {
     pub fn write_code(&mut self, code_str: &str) {
         self.code += &format!("{}\n", code_str);
     }
 
-    pub fn compile_math_operator(&mut self, operator_str: &str) {
-        let vm_operator = match operator_str {
-            "+" => "add",
-            "-" => "sub",
-            "*" => "call Math.multiply 2",
-            "/" => "call Math.divide 2",
-            "&gt;" => "gt",
-            "&lt;" => "lt",
-            "=" => "eq",
-            "&amp;" => "and",
-            "|" => "or",
-            _ => panic!("Got an invalid operator: '{}'", operator_str),
-        };
-        self.write_code(vm_operator);
+    /// Writes the vm code for the class, undeclared variables end up in `diagnostics`
+    pub fn generate_class(&mut self, class: &Class) {
+        let redeclared = self.declare_class_variables(class);
+        self.report_redeclared(redeclared, "class");
+        if self.debug {
+            println!("Class Symbol Table:");
+            println!("{:?}", self.class_symbol_table);
+        }
+
+        for subroutine in &class.subroutines {
+            self.generate_subroutine(subroutine);
+            if self.debug {
+                println!("Subroutine symbol table:");
+                println!("{:?}", self.subroutine_symbol_table);
+            }
+        }
+    }
+
+    fn generate_subroutine(&mut self, subroutine: &Subroutine) {
+        let redeclared = self.declare_subroutine_variables(subroutine);
+        self.report_redeclared(redeclared, "subroutine");
+        // branch labels only need to be unique inside a function
+        self.branches.reset();
+
+        let vars = self.subroutine_symbol_table.get_index("var");
+        self.write_code(&format!(
+            "function {}.{} {}",
+            self.class_type, subroutine.name.name, vars
+        ));
+
+        match subroutine.kind {
+            SubroutineKind::Constructor => {
+                let number_of_class_fields = self.class_symbol_table.get_index("field");
+                self.write_code(&format!("push constant {}", number_of_class_fields));
+                self.write_code("call Memory.alloc 1");
+                self.write_code("pop pointer 0");
+            }
+            SubroutineKind::Method => {
+                self.write_code("push argument 0");
+                self.write_code("pop pointer 0");
+            }
+            SubroutineKind::Function => {}
+        }
+
+        self.generate_statements(&subroutine.statements);
+    }
+
+    fn generate_statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            match statement {
+                Statement::Let(statement) => self.generate_let(statement),
+                Statement::If(statement) => self.generate_if(statement),
+                Statement::While(statement) => self.generate_while(statement),
+                Statement::Do(statement) => {
+                    self.generate_call(&statement.call);
+                    self.write_code("pop temp 0");
+                }
+                Statement::Return(statement) => {
+                    match &statement.value {
+                        Some(value) => self.generate_expression(value),
+                        // a void return still has to leave something on the stack
+                        None => self.write_code("push constant 0"),
+                    }
+                    self.write_code("return");
+                }
+            }
+        }
+    }
+
+    fn generate_let(&mut self, statement: &LetStatement) {
+        let symbol = self.lookup_symbol(&statement.name.name, statement.name.span);
+        match &statement.index {
+            // let arr[index] = value
+            // push index
+            // push arr
+            // add
+            // push value
+            // pop temp 0
+            // pop pointer 1
+            // push temp 0
+            // pop that 0
+            Some(index) => {
+                self.generate_expression(index);
+                if let Some(symbol) = &symbol {
+                    self.push_symbol(symbol);
+                }
+                self.write_code("add");
+                self.generate_expression(&statement.value);
+                self.write_code("pop temp 0");
+                self.write_code("pop pointer 1");
+                self.write_code("push temp 0");
+                self.write_code("pop that 0");
+            }
+            None => {
+                self.generate_expression(&statement.value);
+                if let Some(symbol) = &symbol {
+                    self.pop_symbol(symbol);
+                }
+            }
+        }
+    }
+
+    fn generate_if(&mut self, statement: &IfStatement) {
+        // keep the counter for this if, nested ones take the next
+        let current_if_counter = self.branches.if_counter;
+        self.branches.if_counter += 1;
+
+        self.generate_expression(&statement.condition);
+        self.write_code(&format!("if-goto IF_TRUE{}", current_if_counter));
+        self.write_code(&format!("goto IF_FALSE{}", current_if_counter));
+        self.write_code(&format!("label IF_TRUE{}", current_if_counter));
+        self.generate_statements(&statement.then_statements);
+
+        match &statement.else_statements {
+            Some(else_statements) => {
+                self.write_code(&format!("goto IF_END{}", current_if_counter));
+                self.write_code(&format!("label IF_FALSE{}", current_if_counter));
+                self.generate_statements(else_statements);
+                self.write_code(&format!("label IF_END{}", current_if_counter));
+            }
+            None => self.write_code(&format!("label IF_FALSE{}", current_if_counter)),
+        }
+    }
+
+    fn generate_while(&mut self, statement: &WhileStatement) {
+        let current_while_counter = self.branches.while_counter;
+        self.branches.while_counter += 1;
+
+        self.write_code(&format!("label WHILE_EXP{}", current_while_counter));
+        self.generate_expression(&statement.condition);
+        self.write_code("not");
+        self.write_code(&format!("if-goto WHILE_END{}", current_while_counter));
+        self.generate_statements(&statement.statements);
+        self.write_code(&format!("goto WHILE_EXP{}", current_while_counter));
+        self.write_code(&format!("label WHILE_END{}", current_while_counter));
+    }
+
+    fn generate_expression(&mut self, expression: &Expression) {
+        self.generate_term(&expression.term);
+        for (op, term) in &expression.operations {
+            self.generate_term(term);
+            self.compile_math_operator(*op);
+        }
+    }
+
+    fn generate_term(&mut self, term: &Term) {
+        match &term.kind {
+            TermKind::IntegerConstant(constant) => {
+                if constant_value(term).is_none_or(|value| value > MAX_CONSTANT) {
+                    self.diagnostics.push(Diagnostic::new(
+                        term.span,
+                        format!(
+                            "{} is too big, integer constants go from 0 to {}",
+                            constant, MAX_CONSTANT
+                        ),
+                    ));
+                }
+                self.write_code(&format!("push constant {}", constant))
+            }
+            TermKind::StringConstant(string) => self.compile_string(string),
+            TermKind::Keyword(keyword) => self.compile_keyword(*keyword),
+            TermKind::Variable(name) => {
+                if let Some(symbol) = self.lookup_symbol(&name.name, term.span) {
+                    self.push_symbol(&symbol);
+                }
+            }
+            // arr[index]
+            // push index
+            // push arr
+            // add
+            // pop pointer 1
+            // push that 0
+            TermKind::ArrayAccess(name, index) => {
+                let symbol = self.lookup_symbol(&name.name, name.span);
+                self.generate_expression(index);
+                if let Some(symbol) = &symbol {
+                    self.push_symbol(symbol);
+                }
+                self.write_code("add");
+                self.write_code("pop pointer 1");
+                self.write_code("push that 0");
+            }
+            TermKind::Call(call) => self.generate_call(call),
+            TermKind::Parenthesized(expression) => self.generate_expression(expression),
+            // 32768 doesn't fit in a constant, but -32768 is 32767 with every bit flipped
+            TermKind::Unary(UnaryOp::Neg, operand)
+                if constant_value(operand) == Some(MAX_CONSTANT + 1) =>
+            {
+                self.write_code(&format!("push constant {}", MAX_CONSTANT));
+                self.write_code("not");
+            }
+            TermKind::Unary(op, term) => {
+                self.generate_term(term);
+                self.compile_unary_op(*op);
+            }
+        }
+    }
+
+    fn generate_call(&mut self, call: &SubroutineCall) {
+        let (class_name, target) = self.resolve_call(call);
+        let mut expression_count = call.arguments.len();
+        match &target {
+            // a method of the current object, need to push in this
+            CallTarget::This => {
+                self.write_code("push pointer 0");
+                expression_count += 1;
+            }
+            // a method of the object in the variable
+            CallTarget::Variable(symbol) => {
+                self.push_symbol(symbol);
+                expression_count += 1;
+            }
+            // a function or constructor
+            CallTarget::Class => {}
+        }
+
+        for argument in &call.arguments {
+            self.generate_expression(argument);
+        }
+        self.write_code(&format!(
+            "call {}.{} {}",
+            class_name, call.name.name, expression_count
+        ));
+    }
+
+    /// Notes an error for every name that was declared twice in the class or subroutine
+    fn report_redeclared(&mut self, names: Vec<&Identifier>, scope: &str) {
+        for name in names {
+            self.diagnostics.push(Diagnostic::new(
+                name.span,
+                format!("'{}' is already declared in this {}", name.name, scope),
+            ));
+        }
     }
 
-    pub fn compile_constant(&mut self, constant: &str) {
-        self.write_code(&format!("push constant {}", constant));
+    /// The variable's symbol, or an error noted if it was never declared
+    fn lookup_symbol(&mut self, name: &str, span: Span) -> Option<Symbol> {
+        let symbol = self.get_symbol(name).cloned();
+        if symbol.is_none() {
+            self.diagnostics.push(Diagnostic::new(
+                span,
+                format!("'{}' isn't a declared variable", name),
+            ));
+        }
+        symbol
+    }
+
+    pub fn compile_math_operator(&mut self, op: Op) {
+        let vm_operator = match op {
+            Op::Add => "add",
+            Op::Sub => "sub",
+            Op::Mul if self.native_math => "mul",
+            Op::Div if self.native_math => "div",
+            Op::Mul => "call Math.multiply 2",
+            Op::Div => "call Math.divide 2",
+            Op::Gt => "gt",
+            Op::Lt => "lt",
+            Op::Eq => "eq",
+            Op::And => "and",
+            Op::Or => "or",
+        };
+        self.write_code(vm_operator);
     }
 
     pub fn compile_string(&mut self, string: &str) {
@@ -43,31 +304,22 @@ impl Compiler {
         }
     }
 
-    pub fn compile_return(&mut self) {
-        self.write_code("return");
-    }
-
-    pub fn compile_keyword(&mut self, keyword: &str) {
+    pub fn compile_keyword(&mut self, keyword: KeywordConstant) {
         match keyword {
-            "true" => {
+            KeywordConstant::True => {
                 self.write_code("push constant 0");
                 self.write_code("not");
             }
-            "false" => self.write_code("push constant 0"),
-            "null" => self.write_code("push constant 0"),
-            "this" => self.write_code("push pointer 0"), // when returning this
-            _ => panic!(
-                "Came across an unfamiliar keyword we shouldn't see: '{}'",
-                keyword
-            ),
+            KeywordConstant::False => self.write_code("push constant 0"),
+            KeywordConstant::Null => self.write_code("push constant 0"),
+            KeywordConstant::This => self.write_code("push pointer 0"), // when returning this
         }
     }
 
-    pub fn compile_unary_op(&mut self, op: &str) {
+    pub fn compile_unary_op(&mut self, op: UnaryOp) {
         match op {
-            "-" => self.write_code("neg"),
-            "~" => self.write_code("not"),
-            _ => panic!("Was given an op that wasn't unary: '{}'", op),
+            UnaryOp::Neg => self.write_code("neg"),
+            UnaryOp::Not => self.write_code("not"),
         }
     }
 
@@ -83,3 +335,85 @@ impl Compiler {
         self.write_code(&format!("push {} {}", kind, index))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::compiler::parser::Compiler;
+
+    #[test]
+    fn test_redeclared_variables() {
+        let source = "class Main {
+    field int size, size;
+    constructor Main new() { return this; }
+    function void main(int a) {
+        var int a, b;
+        var int b;
+        let b = a;
+        return;
+    }
+}";
+        let mut compiler = Compiler::new(PathBuf::from("Main.jack"), false);
+        compiler.lints = false;
+        compiler.compile_source(source);
+
+        let errors: Vec<String> = compiler
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "2:21: 'size' is already declared in this class",
+                "5:17: 'a' is already declared in this subroutine",
+                "6:17: 'b' is already declared in this subroutine",
+            ]
+        );
+        // the repeats don't take up a field or local of their own
+        assert!(
+            compiler
+                .code
+                .contains("push constant 1\ncall Memory.alloc 1\n")
+        );
+        assert!(
+            compiler
+                .code
+                .contains("function Main.main 1\npush argument 0\npop local 0\n")
+        );
+    }
+
+    #[test]
+    fn test_integer_constants() {
+        let source = "class Main {
+    function int main() {
+        var int a;
+        let a = -32768 + 32767;
+        let a = 40000 - 32768;
+        return a + 123456789012345678901234567890;
+    }
+}";
+        let mut compiler = Compiler::new(PathBuf::from("Main.jack"), false);
+        compiler.compile_source(source);
+
+        let errors: Vec<String> = compiler
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "5:17: 40000 is too big, integer constants go from 0 to 32767",
+                "5:25: 32768 is too big, integer constants go from 0 to 32767",
+                "6:20: 123456789012345678901234567890 is too big, integer constants go from 0 to 32767",
+            ]
+        );
+        assert!(
+            compiler
+                .code
+                .contains("push constant 32767\nnot\npush constant 32767\nadd\n")
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use crate::compiler::ast::{
+    Class, Expression, KeywordConstant, Statement, Subroutine, SubroutineKind, TermKind,
+};
+use crate::compiler::diagnostics::Diagnostic;
+use crate::compiler::parser::Compiler;
+
+/// `while (true)`, which Jack can only leave with a return, or an `if (true)` whose else
+/// block never runs
+fn is_always_true(condition: &Expression) -> bool {
+    condition.operations.is_empty()
+        && matches!(
+            condition.term.kind,
+            TermKind::Keyword(KeywordConstant::True)
+        )
+}
+
+/// Just `this`, as a constructor returns
+fn is_this(value: &Expression) -> bool {
+    value.operations.is_empty()
+        && matches!(value.term.kind, TermKind::Keyword(KeywordConstant::This))
+}
+
+impl Compiler {
+    /// Errors for subroutines that can run off their end and constructors that don't
+    /// return `this`, and warnings for statements that can never run
+    pub fn check_control_flow(&mut self, class: &Class) {
+        for subroutine in &class.subroutines {
+            // the vm would carry on into whatever function comes next
+            if self.check_reachability(subroutine, &subroutine.statements) {
+                self.diagnostics.push(Diagnostic::new(
+                    subroutine.name.span,
+                    format!(
+                        "'{}' can reach its end without returning",
+                        subroutine.name.name
+                    ),
+                ));
+            }
+        }
+    }
+
+    /// Whether running the statements can carry on past the last one
+    fn check_reachability(&mut self, subroutine: &Subroutine, statements: &[Statement]) -> bool {
+        let mut reachable = true;
+        for statement in statements {
+            if !reachable {
+                // the rest of the block is just as unreachable, once is enough
+                self.diagnostics.push(Diagnostic::warning(
+                    statement.span(),
+                    String::from("this statement can never be reached"),
+                ));
+                return false;
+            }
+            reachable = match statement {
+                Statement::Let(_) | Statement::Do(_) => true,
+                Statement::If(statement) => {
+                    let then_reachable =
+                        self.check_reachability(subroutine, &statement.then_statements);
+                    let else_reachable = match &statement.else_statements {
+                        Some(else_statements) => {
+                            self.check_reachability(subroutine, else_statements)
+                        }
+                        None => true,
+                    };
+                    if is_always_true(&statement.condition) {
+                        then_reachable
+                    } else {
+                        then_reachable || else_reachable
+                    }
+                }
+                Statement::While(statement) => {
+                    self.check_reachability(subroutine, &statement.statements);
+                    !is_always_true(&statement.condition)
+                }
+                Statement::Return(statement) => {
+                    if subroutine.kind == SubroutineKind::Constructor
+                        && let Some(value) = &statement.value
+                        && !is_this(value)
+                    {
+                        self.diagnostics.push(Diagnostic::new(
+                            value.term.span,
+                            format!(
+                                "the constructor '{}' has to return this",
+                                subroutine.name.name
+                            ),
+                        ));
+                    }
+                    false
+                }
+            };
+        }
+        reachable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::compiler::parser::Compiler;
+
+    #[test]
+    fn test_check_control_flow() {
+        let source = "class Shape {
+    field int size;
+    constructor Shape new() {
+        let size = 1;
+        return size;
+    }
+    method int area() {
+        if (size > 0) {
+            return size * size;
+        } else {
+            return 0;
+        }
+        let size = 0;
+    }
+    method void draw() {
+        do Output.printInt(area());
+        return;
+    }
+    function int count(int n) {
+        if (n > 0) {
+            return Output.printInt(n);
+        }
+    }
+    function void run() {
+        do draw();
+        while (true) {
+            do Sys.wait(1);
+        }
+        return;
+    }
+    function int first() {
+        if (true) {
+            return 1;
+        }
+    }
+}";
+        let mut compiler = Compiler::new(PathBuf::from("Shape.jack"), false);
+        compiler.lints = false;
+        compiler.compile_source(source);
+
+        let diagnostics: Vec<String> = compiler
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            diagnostics,
+            [
+                "5:16: 'new' should return a Shape but returns an int",
+                "5:16: the constructor 'new' has to return this",
+                "13:9: warning: this statement can never be reached",
+                "19:18: 'count' can reach its end without returning",
+                "21:20: 'Output.printInt' is void, so it has no value to use",
+                "25:12: 'Shape.draw' is a method, so a function has to call it on a Shape object",
+                "29:9: warning: this statement can never be reached",
+            ]
+        );
+    }
+}
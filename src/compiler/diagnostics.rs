@@ -0,0 +1,136 @@
+use std::fmt::Display;
+use std::path::Path;
+
+use serde::Serialize;
+
+/// Where a piece of source starts, counting lines and columns from 1, and how many characters it covers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize, length: usize) -> Self {
+        Span {
+            line,
+            column,
+            length,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Stops any files from being written
+    Error,
+    /// Reported, but the file still compiles
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in a .jack file, pointing at the source that caused it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+    pub severity: Severity,
+}
+
+impl Diagnostic {
+    pub fn new(span: Span, message: String) -> Self {
+        Diagnostic {
+            span,
+            message,
+            severity: Severity::Error,
+        }
+    }
+
+    pub fn warning(span: Span, message: String) -> Self {
+        Diagnostic {
+            span,
+            message,
+            severity: Severity::Warning,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// The message followed by the offending line with a caret under the span:
+    ///
+    /// ```text
+    /// error: expected ';' but found 'let'
+    ///  --> Main.jack:5:9
+    ///   |
+    /// 5 |         let x = 1
+    ///   |         ^^^
+    /// ```
+    pub fn render(&self, file: &Path, source: &str) -> String {
+        let file_name = file
+            .file_name()
+            .unwrap_or(file.as_os_str())
+            .to_string_lossy();
+        let line_number = self.span.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        let mut output = format!("{}: {}\n", self.severity, self.message);
+        output += &format!(
+            "{}--> {}:{}:{}\n",
+            gutter, file_name, self.span.line, self.span.column
+        );
+        let Some(line) = source.lines().nth(self.span.line.wrapping_sub(1)) else {
+            return output;
+        };
+        // tabs are kept so the caret lines up however wide they are shown
+        let indent: String = line
+            .chars()
+            .take(self.span.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        output += &format!("{} |\n", gutter);
+        output += &format!("{} | {}\n", line_number, line);
+        output += &format!(
+            "{} | {}{}\n",
+            gutter,
+            indent,
+            "^".repeat(self.span.length.max(1))
+        );
+        output
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: ", self.span.line, self.span.column)?;
+        if self.severity == Severity::Warning {
+            write!(f, "warning: ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "class Main {\n    let x = 1\n}\n";
+        let diagnostic = Diagnostic::new(Span::new(2, 9, 1), String::from("expected ';'"));
+        assert_eq!(
+            diagnostic.render(Path::new("src/Main.jack"), source),
+            "error: expected ';'\n --> Main.jack:2:9\n  |\n2 |     let x = 1\n  |         ^\n"
+        );
+    }
+}
@@ -16,16 +16,18 @@ pub fn valid_files(file: &Path) -> Option<Vec<PathBuf>> {
         {
             let entry_path = entry.path();
             let extension = entry_path.extension();
-            if let Some(extension) = extension {
-                if extension == "jack" {
-                    files.push(entry.path().to_path_buf())
-                }
+            if let Some(extension) = extension
+                && extension == "jack"
+            {
+                files.push(entry.path().to_path_buf())
             }
         }
 
         if files.is_empty() {
             return None;
         }
+        // read_dir order depends on the filesystem, so sort for reproducible output
+        files.sort();
         return Some(files);
     }
 
@@ -49,3 +51,16 @@ pub fn valid_files(file: &Path) -> Option<Vec<PathBuf>> {
     println!("Could not find a valid file");
     None
 }
+
+/// The other .jack files in the folder of a single .jack file
+pub fn sibling_files(file: &Path) -> Vec<PathBuf> {
+    let folder = match file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    valid_files(folder)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|sibling| sibling.file_name() != file.file_name())
+        .collect()
+}
@@ -3,419 +3,262 @@ use std::iter::Peekable;
 use crate::compiler::parser::Compiler;
 
 use super::{
-    symbol_table::{SymbolCategory, SymbolTable},
+    ast::{
+        Class, ClassVarDec, ClassVarKind, Identifier, Parameter, Subroutine, SubroutineKind, Type,
+        VarDec,
+    },
+    diagnostics::{Diagnostic, Span},
     tokens::{Token, TokenType},
 };
 
 impl Compiler {
-    pub fn save_to_output(&mut self, grammar_string: &str) {
-        let mut spaces = String::new();
-        let spaces_count = self.output_padding;
-        for _ in 0..spaces_count {
-            spaces += " ";
-        }
-        self.output += &format!("{}{}\n", spaces, grammar_string);
-    }
-
-    pub fn parse_tokens_to_grammar(&mut self) {
+    /// Builds the syntax tree for the file's tokens
+    pub fn parse_class(&mut self) -> Result<Class, Diagnostic> {
         let tokens = self.tokens.clone();
         let mut tokens_iter = tokens.iter().peekable();
 
-        self.save_to_output("<class>");
-        self.output_padding += 2;
-
         // class
-        self.process_specific(&mut tokens_iter, "class".to_string(), TokenType::Keyword);
+        self.expect(&mut tokens_iter, "class", TokenType::Keyword)?;
         // class name
-        self.class_type = self.process_type(&mut tokens_iter, TokenType::Identifier);
+        let name = self.expect_identifier(&mut tokens_iter)?;
         // {
-        self.process_specific(&mut tokens_iter, String::from("{"), TokenType::Symbol);
+        self.expect(&mut tokens_iter, "{", TokenType::Symbol)?;
         // class variable declarations
-        self.process_class_variable_declarations(&mut tokens_iter);
-        if self.debug {
-            println!("Class Symbol Table:");
-            println!("{:?}", self.class_symbol_table);
-        }
-
+        let variables = self.parse_class_variable_declarations(&mut tokens_iter)?;
         // subroutine declarations
-        self.process_subroutine_declarations(&mut tokens_iter);
-
-        // parameter list
-        // subroutine body
-        // variable declarations
-
-        self.process_specific(&mut tokens_iter, String::from("}"), TokenType::Symbol);
-        self.output_padding -= 2;
-        self.save_to_output("</class>");
-    }
-
-    fn process_subroutine_declarations<'a, I: Iterator<Item = &'a Token>>(
-        &mut self,
-        tokens_iter: &mut Peekable<I>,
-    ) {
-        // if we see 'static' or 'field' then we process next 4 tokens, repeating
-        // static or field
-        // type
-        // variable name
-        // ;
-        let mut peek = tokens_iter.peek().cloned();
-        loop {
-            match peek {
-                Some(p) => {
-                    if (p.token_str == "constructor")
-                        | (p.token_str == "function")
-                        | (p.token_str == "method")
-                    {
-                        // need to reset symbol table for subroutine
-                        self.subroutine_symbol_table = SymbolTable::new();
-                        if p.token_str == "method" {
-                            self.subroutine_symbol_table.insert_symbol(
-                                String::from("this"),
-                                self.class_type.clone(),
-                                String::from("arg"),
-                                0,
-                            );
-                            self.subroutine_symbol_table.increment_index("arg");
-                        }
-
-                        // also reset the branch counts
-                        self.branches.reset();
-
-                        self.save_to_output("<subroutineDec>");
-                        self.output_padding += 2;
-
-                        // process constructor, function or method
-                        self.process_type(tokens_iter, TokenType::Keyword);
-                        // the type associated with the function
-                        self.process_next(tokens_iter);
-
-                        // name of the function
-                        let name = self.process_type(tokens_iter, TokenType::Identifier);
-
-                        // parameters
-                        self.process_specific(tokens_iter, String::from("("), TokenType::Symbol);
-                        self.process_parameter_list(tokens_iter);
-                        self.process_specific(tokens_iter, String::from(")"), TokenType::Symbol);
-
-                        // subroutineBody
-                        self.process_subroutine_body(tokens_iter, &name, &p.token_str);
-
-                        self.output_padding -= 2;
-                        self.save_to_output("</subroutineDec>");
-                        peek = tokens_iter.peek().cloned();
+        let subroutines = self.parse_subroutine_declarations(&mut tokens_iter)?;
+        self.expect(&mut tokens_iter, "}", TokenType::Symbol)?;
 
-                        if self.debug {
-                            println!("Subroutine symbol table:");
-                            println!("{:?}", self.subroutine_symbol_table);
-                        }
-                    } else {
-                        return;
-                    }
-                }
-                None => {
-                    return;
-                }
-            }
+        if let Some(extra) = tokens_iter.next() {
+            return Err(self.error(extra, "expected the end of the file after the class"));
         }
+        Ok(Class {
+            name,
+            variables,
+            subroutines,
+        })
     }
 
-    fn process_subroutine_body<'a, I: Iterator<Item = &'a Token>>(
+    fn parse_class_variable_declarations<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-        name: &str,
-        function_type: &str,
-    ) {
-        self.save_to_output("<subroutineBody>");
-        self.output_padding += 2;
-
-        self.process_specific(tokens_iter, String::from("{"), TokenType::Symbol);
-
-        // first check for var declarations
-        self.process_subroutine_variable_declarations(tokens_iter);
-        // then use our symbol table to find number of var declarations
-        let mut vars = 0;
-        for symbol in self.subroutine_symbol_table.table.values() {
-            if let SymbolCategory::Var = symbol.kind {
-                vars += 1;
-            }
-        }
-
-        // begin our function definition
-        self.write_code(&format!("function {}.{} {}", self.class_type, name, vars));
-
-        // add some code for if dealing with a constructor
-        if function_type == "constructor" {
-            let mut number_of_class_fields = 0;
-            for symbol in self.class_symbol_table.table.values() {
-                if let SymbolCategory::Field = symbol.kind {
-                    number_of_class_fields += 1;
-                }
-            }
-            self.write_code(&format!("push constant {}", number_of_class_fields));
-            self.write_code("call Memory.alloc 1");
-            self.write_code("pop pointer 0");
-        }
-
-        if function_type == "method" {
-            self.write_code("push argument 0");
-            self.write_code("pop pointer 0");
+    ) -> Result<Vec<ClassVarDec>, Diagnostic> {
+        // static or field, type, names, ;
+        let mut declarations = vec![];
+        loop {
+            let kind = match self.peek_token(tokens_iter)?.token_str.as_str() {
+                "static" => ClassVarKind::Static,
+                "field" => ClassVarKind::Field,
+                _ => return Ok(declarations),
+            };
+            tokens_iter.next();
+            let var_type = self.parse_type(tokens_iter, false)?;
+            let names = self.parse_variable_names(tokens_iter)?;
+            declarations.push(ClassVarDec {
+                kind,
+                var_type,
+                names,
+            });
         }
-
-        // Then iterate through statements
-        self.process_statements(tokens_iter);
-        self.process_specific(tokens_iter, String::from("}"), TokenType::Symbol);
-
-        self.output_padding -= 2;
-        self.save_to_output("</subroutineBody>");
     }
 
-    fn process_subroutine_variable_declarations<'a, I: Iterator<Item = &'a Token>>(
+    fn parse_subroutine_declarations<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-    ) {
-        // if we see 'var' then we process next 4 tokens, repeating
-        // var
-        // type
-        // variable name
-        // ;
-        let mut peek = tokens_iter.peek().cloned();
+    ) -> Result<Vec<Subroutine>, Diagnostic> {
+        let mut subroutines = vec![];
         loop {
-            match peek {
-                Some(p) => {
-                    if p.token_str == "var" {
-                        // we want to get for this particular kind (static, field, var arg) index
-                        let mut current_index =
-                            self.subroutine_symbol_table.get_index(&p.token_str);
-
-                        self.save_to_output("<varDec>");
-                        self.output_padding += 2;
-                        // var
-                        let token_kind = self.process_type(tokens_iter, TokenType::Keyword);
-                        // variable type
-                        let token_type = self.process_next(tokens_iter);
-                        // names
-                        let token_names = self.process_variable_names(tokens_iter);
-
-                        for name in token_names {
-                            // push into symbol table
-                            self.subroutine_symbol_table.insert_symbol(
-                                name,
-                                token_type.clone(),
-                                token_kind.clone(),
-                                current_index,
-                            );
-
-                            current_index += 1;
-                            self.subroutine_symbol_table.increment_index(&p.token_str);
-                        }
-
-                        self.output_padding -= 2;
-                        self.save_to_output("</varDec>");
-                        peek = tokens_iter.peek().cloned();
-                    } else {
-                        return;
-                    }
-                }
-                None => {
-                    return;
-                }
-            }
+            let kind = match self.peek_token(tokens_iter)?.token_str.as_str() {
+                "constructor" => SubroutineKind::Constructor,
+                "function" => SubroutineKind::Function,
+                "method" => SubroutineKind::Method,
+                _ => return Ok(subroutines),
+            };
+            tokens_iter.next();
+
+            // the type associated with the function
+            let return_type = self.parse_type(tokens_iter, true)?;
+            // name of the function
+            let name = self.expect_identifier(tokens_iter)?;
+
+            // parameters
+            self.expect(tokens_iter, "(", TokenType::Symbol)?;
+            let parameters = self.parse_parameter_list(tokens_iter)?;
+            self.expect(tokens_iter, ")", TokenType::Symbol)?;
+
+            // subroutineBody
+            self.expect(tokens_iter, "{", TokenType::Symbol)?;
+            let locals = self.parse_subroutine_variable_declarations(tokens_iter)?;
+            let statements = self.parse_statements(tokens_iter)?;
+            self.expect(tokens_iter, "}", TokenType::Symbol)?;
+
+            subroutines.push(Subroutine {
+                kind,
+                return_type,
+                name,
+                parameters,
+                locals,
+                statements,
+            });
         }
     }
 
-    fn process_parameter_list<'a, I: Iterator<Item = &'a Token>>(
+    fn parse_parameter_list<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-    ) {
-        self.save_to_output("<parameterList>");
-        self.output_padding += 2;
-
-        let parenthesis_close_peek = tokens_iter.peek().cloned().unwrap();
-        if parenthesis_close_peek.token_str == ")" {
-            self.output_padding -= 2;
-            self.save_to_output("</parameterList>");
-            return;
+    ) -> Result<Vec<Parameter>, Diagnostic> {
+        let mut parameters = vec![];
+        if self.peek_token(tokens_iter)?.token_str == ")" {
+            return Ok(parameters);
         }
 
-        // we have parameters! let's get them
-        let mut current_index = self.subroutine_symbol_table.get_index("arg");
         loop {
-            // parameter type
-            let token_type = self.process_next(tokens_iter);
-            // paramter name
-            let token_name = self.process_type(tokens_iter, TokenType::Identifier);
-
-            // add to symbol table
-            self.subroutine_symbol_table.insert_symbol(
-                token_name,
-                token_type,
-                String::from("arg"),
-                current_index,
-            );
-
-            // incremnet index
-            current_index += 1;
-            self.subroutine_symbol_table.increment_index("arg");
+            let var_type = self.parse_type(tokens_iter, false)?;
+            let name = self.expect_identifier(tokens_iter)?;
+            parameters.push(Parameter { var_type, name });
 
             // look for comma
-            let comma_peek = tokens_iter.peek().cloned().unwrap();
-            if comma_peek.token_str == "," {
-                self.process_specific(tokens_iter, String::from(","), TokenType::Symbol);
-            } else {
-                break;
+            if self.peek_token(tokens_iter)?.token_str != "," {
+                return Ok(parameters);
             }
+            tokens_iter.next();
         }
+    }
 
-        self.output_padding -= 2;
-        self.save_to_output("</parameterList>");
+    fn parse_subroutine_variable_declarations<'a, I: Iterator<Item = &'a Token>>(
+        &mut self,
+        tokens_iter: &mut Peekable<I>,
+    ) -> Result<Vec<VarDec>, Diagnostic> {
+        // var, type, names, ;
+        let mut declarations = vec![];
+        while self.peek_token(tokens_iter)?.token_str == "var" {
+            tokens_iter.next();
+            let var_type = self.parse_type(tokens_iter, false)?;
+            let names = self.parse_variable_names(tokens_iter)?;
+            declarations.push(VarDec { var_type, names });
+        }
+        Ok(declarations)
     }
 
-    fn process_class_variable_declarations<'a, I: Iterator<Item = &'a Token>>(
+    /// One or more names separated by commas and ending with a `;`
+    fn parse_variable_names<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-    ) {
-        // if we see 'static' or 'field' then we process next 4 tokens, repeating
-        // static or field
-        // type
-        // variable name
-        // ;
-        let mut peek = tokens_iter.peek().cloned();
+    ) -> Result<Vec<Identifier>, Diagnostic> {
+        let mut names = vec![self.expect_identifier(tokens_iter)?];
         loop {
-            match peek {
-                Some(p) => {
-                    if (p.token_str == "static") | (p.token_str == "field") {
-                        // we want to get for this particular kind (static, field, var arg) index
-                        let mut current_index = self.class_symbol_table.get_index(&p.token_str);
-
-                        self.save_to_output("<classVarDec>");
-                        self.output_padding += 2;
-                        // static or field
-                        let token_kind = self.process_type(tokens_iter, TokenType::Keyword);
-                        // variable type
-                        let token_type = self.process_next(tokens_iter);
-                        // variable name
-                        let token_names = self.process_variable_names(tokens_iter);
-
-                        for name in token_names {
-                            // push into symbol table
-                            self.class_symbol_table.insert_symbol(
-                                name,
-                                token_type.clone(),
-                                token_kind.clone(),
-                                current_index,
-                            );
-
-                            current_index += 1;
-                            self.class_symbol_table.increment_index(&p.token_str);
-                        }
-
-                        self.output_padding -= 2;
-                        self.save_to_output("</classVarDec>");
-                        peek = tokens_iter.peek().cloned();
-                    } else {
-                        return;
-                    }
-                }
-                None => {
-                    return;
+            let next_symbol = self.next_token(tokens_iter)?;
+            match next_symbol.token_str.as_str() {
+                "," => names.push(self.expect_identifier(tokens_iter)?),
+                ";" => return Ok(names),
+                _ => {
+                    return Err(
+                        self.error(next_symbol, "expected ',' or ';' after a variable name")
+                    );
                 }
             }
         }
     }
 
-    fn process_variable_names<'a, I: Iterator<Item = &'a Token>>(
+    fn parse_type<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-    ) -> Vec<String> {
-        let mut names = vec![];
-
-        let name = self.process_type(tokens_iter, TokenType::Identifier);
-        names.push(name);
-
-        let mut next_symbol = tokens_iter.next().unwrap();
-        loop {
-            if next_symbol.token_str == "," {
-                // deal with comma
-                self.save_to_output(&next_symbol.to_string());
-                // deal with name
-                let name = self.process_type(tokens_iter, TokenType::Identifier);
-                names.push(name);
-                // check for next token
-                next_symbol = tokens_iter.next().unwrap();
-            } else if next_symbol.token_str == ";" {
-                self.save_to_output(&next_symbol.to_string());
-                break;
-            } else {
-                panic!("Found a symbol we shouldn't be seeing {}", next_symbol);
+        allow_void: bool,
+    ) -> Result<Type, Diagnostic> {
+        let token = self.next_token(tokens_iter)?;
+        let var_type = match (&token.token_type, token.token_str.as_str()) {
+            (TokenType::Keyword, "int") => Type::Int,
+            (TokenType::Keyword, "char") => Type::Char,
+            (TokenType::Keyword, "boolean") => Type::Boolean,
+            (TokenType::Keyword, "void") if allow_void => Type::Void,
+            (TokenType::Identifier, name) => Type::Class(name.to_string()),
+            _ => {
+                return Err(self.error(
+                    token,
+                    &format!("expected a type but found '{}'", token.source_text()),
+                ));
             }
-        }
-
-        names
+        };
+        Ok(var_type)
     }
 
-    pub fn process_specific<'a, I: Iterator<Item = &'a Token>>(
-        &mut self,
-        tokens_iter: &mut I,
-        expected_token_str: String,
+    /// Takes the next token if it's the expected one. A wrong token is left in place,
+    /// it's often the start of the next statement
+    pub fn expect<'a, I: Iterator<Item = &'a Token>>(
+        &self,
+        tokens_iter: &mut Peekable<I>,
+        expected_token_str: &str,
         expected_token_type: TokenType,
-    ) -> String {
-        let next_token = tokens_iter.next().unwrap();
-        let expected_token = Token::new(expected_token_str, expected_token_type);
-
-        if *next_token != expected_token {
-            panic!(
-                "{}\nThe token should be a '{}'. Found '{}'\nNext tokens are:\n{:?}\n{:?}\n{:?}",
-                self.file_path.display(),
-                expected_token,
+    ) -> Result<&'a Token, Diagnostic> {
+        let next_token = self.peek_token(tokens_iter)?;
+
+        if next_token.token_str != expected_token_str
+            || next_token.token_type != expected_token_type
+        {
+            let expected = Token::new(expected_token_str.to_string(), expected_token_type);
+            return Err(self.error(
                 next_token,
-                tokens_iter.next(),
-                tokens_iter.next(),
-                tokens_iter.next()
-            )
+                &format!(
+                    "expected '{}' but found '{}'",
+                    expected.source_text(),
+                    next_token.source_text()
+                ),
+            ));
         }
-        self.save_to_output(&next_token.to_string());
-        next_token.token_str.clone()
+        tokens_iter.next();
+        Ok(next_token)
     }
 
-    pub fn process_type<'a, I: Iterator<Item = &'a Token>>(
-        &mut self,
-        tokens_iter: &mut I,
-        expected_token_type: TokenType,
-    ) -> String {
-        let next_token = tokens_iter.next().unwrap();
+    pub fn expect_identifier<'a, I: Iterator<Item = &'a Token>>(
+        &self,
+        tokens_iter: &mut Peekable<I>,
+    ) -> Result<Identifier, Diagnostic> {
+        let next_token = self.peek_token(tokens_iter)?;
 
-        if next_token.token_type != expected_token_type {
-            panic!(
-                "{}\nThe token should be a '{}'. Found '{}'",
-                self.file_path.display(),
-                expected_token_type,
-                next_token
-            )
+        if next_token.token_type != TokenType::Identifier {
+            return Err(self.error(
+                next_token,
+                &format!(
+                    "expected {} but found '{}'",
+                    TokenType::Identifier.description(),
+                    next_token.source_text()
+                ),
+            ));
         }
-        self.save_to_output(&next_token.to_string());
-        next_token.token_str.clone()
+        tokens_iter.next();
+        Ok(Identifier {
+            name: next_token.token_str.clone(),
+            span: next_token.span,
+        })
     }
 
-    pub fn process_next<'a, I: Iterator<Item = &'a Token>>(
-        &mut self,
+    pub fn next_token<'a, I: Iterator<Item = &'a Token>>(
+        &self,
         tokens_iter: &mut I,
-    ) -> String {
-        let next_token = tokens_iter.next().unwrap();
-        self.save_to_output(&next_token.to_string());
-        next_token.token_str.clone()
+    ) -> Result<&'a Token, Diagnostic> {
+        tokens_iter.next().ok_or_else(|| self.end_of_file())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn save_to_output() {
-        let grammar_string = "<class>";
-        let mut tabs = String::new();
-        let tabs_count = 4;
-        for _ in 0..tabs_count {
-            tabs += " ";
-        }
-        assert_eq!(format!("{}{}\n", tabs, grammar_string), "    <class>\n");
+    pub fn peek_token<'a, I: Iterator<Item = &'a Token>>(
+        &self,
+        tokens_iter: &mut Peekable<I>,
+    ) -> Result<&'a Token, Diagnostic> {
+        tokens_iter
+            .peek()
+            .copied()
+            .ok_or_else(|| self.end_of_file())
+    }
+
+    pub fn error(&self, token: &Token, message: &str) -> Diagnostic {
+        Diagnostic::new(token.span, message.to_string())
+    }
+
+    /// Points just past the last token
+    fn end_of_file(&self) -> Diagnostic {
+        let span = match self.tokens.last() {
+            Some(token) => Span::new(token.span.line, token.span.column + token.span.length, 1),
+            None => Span::new(1, 1, 1),
+        };
+        Diagnostic::new(span, String::from("unexpected end of file"))
     }
 }
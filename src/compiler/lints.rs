@@ -0,0 +1,231 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::compiler::ast::{
+    Class, Expression, Identifier, Statement, SubroutineCall, Term, TermKind,
+};
+use crate::compiler::diagnostics::Diagnostic;
+use crate::compiler::parser::Compiler;
+use crate::compiler::symbol_table::SymbolCategory;
+
+/// A variable being read or written, in the order the vm code does it
+struct Access<'a> {
+    name: &'a Identifier,
+    write: bool,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    read: bool,
+    written: bool,
+}
+
+impl Usage {
+    /// Why the variable is pointless, if it is
+    fn problem(&self) -> Option<&'static str> {
+        match (self.read, self.written) {
+            (true, _) => None,
+            (false, true) => Some("is assigned but never read"),
+            (false, false) => Some("is never used"),
+        }
+    }
+}
+
+fn statement_accesses<'a>(statements: &'a [Statement], accesses: &mut Vec<Access<'a>>) {
+    for statement in statements {
+        match statement {
+            Statement::Let(statement) => {
+                if let Some(index) = &statement.index {
+                    expression_accesses(index, accesses);
+                    // writing to an element reads the array's address
+                    accesses.push(Access {
+                        name: &statement.name,
+                        write: false,
+                    });
+                }
+                expression_accesses(&statement.value, accesses);
+                if statement.index.is_none() {
+                    accesses.push(Access {
+                        name: &statement.name,
+                        write: true,
+                    });
+                }
+            }
+            Statement::If(statement) => {
+                expression_accesses(&statement.condition, accesses);
+                statement_accesses(&statement.then_statements, accesses);
+                if let Some(else_statements) = &statement.else_statements {
+                    statement_accesses(else_statements, accesses);
+                }
+            }
+            Statement::While(statement) => {
+                expression_accesses(&statement.condition, accesses);
+                statement_accesses(&statement.statements, accesses);
+            }
+            Statement::Do(statement) => call_accesses(&statement.call, accesses),
+            Statement::Return(statement) => {
+                if let Some(value) = &statement.value {
+                    expression_accesses(value, accesses);
+                }
+            }
+        }
+    }
+}
+
+fn expression_accesses<'a>(expression: &'a Expression, accesses: &mut Vec<Access<'a>>) {
+    term_accesses(&expression.term, accesses);
+    for (_, term) in &expression.operations {
+        term_accesses(term, accesses);
+    }
+}
+
+fn term_accesses<'a>(term: &'a Term, accesses: &mut Vec<Access<'a>>) {
+    match &term.kind {
+        TermKind::Variable(name) => accesses.push(Access { name, write: false }),
+        TermKind::ArrayAccess(name, index) => {
+            expression_accesses(index, accesses);
+            accesses.push(Access { name, write: false });
+        }
+        TermKind::Call(call) => call_accesses(call, accesses),
+        TermKind::Parenthesized(expression) => expression_accesses(expression, accesses),
+        TermKind::Unary(_, term) => term_accesses(term, accesses),
+        TermKind::IntegerConstant(_) | TermKind::StringConstant(_) | TermKind::Keyword(_) => {}
+    }
+}
+
+fn call_accesses<'a>(call: &'a SubroutineCall, accesses: &mut Vec<Access<'a>>) {
+    // a receiver that isn't a variable is a class, which won't be in the symbol tables
+    if let Some(receiver) = &call.receiver {
+        accesses.push(Access {
+            name: receiver,
+            write: false,
+        });
+    }
+    for argument in &call.arguments {
+        expression_accesses(argument, accesses);
+    }
+}
+
+impl Compiler {
+    /// Warns about locals that are never read or are read before they're assigned,
+    /// parameters hiding class variables, and class variables that are never read
+    pub fn lint_class(&mut self, class: &Class) {
+        self.declare_class_variables(class);
+        let mut class_usage: HashMap<String, Usage> = HashMap::new();
+
+        for subroutine in &class.subroutines {
+            self.declare_subroutine_variables(subroutine);
+
+            for parameter in &subroutine.parameters {
+                if let Some(symbol) = self.class_symbol_table.get_symbol(&parameter.name.name) {
+                    let kind = match symbol.kind {
+                        SymbolCategory::Static => "static",
+                        _ => "field",
+                    };
+                    let message = format!(
+                        "the parameter '{}' hides the {} with the same name",
+                        parameter.name.name, kind
+                    );
+                    self.diagnostics
+                        .push(Diagnostic::warning(parameter.name.span, message));
+                }
+            }
+
+            let mut accesses = vec![];
+            statement_accesses(&subroutine.statements, &mut accesses);
+            let mut local_usage: HashMap<&str, Usage> = HashMap::new();
+            let mut assigned_or_reported = HashSet::new();
+            for access in accesses {
+                let name = access.name.name.as_str();
+                let usage = match self.subroutine_symbol_table.get_symbol(name) {
+                    Some(symbol) if matches!(symbol.kind, SymbolCategory::Var) => {
+                        // only the first read of each local is worth pointing out
+                        if !access.write && assigned_or_reported.insert(name) {
+                            self.diagnostics.push(Diagnostic::warning(
+                                access.name.span,
+                                format!("'{}' is read before it's assigned", name),
+                            ));
+                        }
+                        local_usage.entry(name).or_default()
+                    }
+                    Some(_) => continue,
+                    None if self.class_symbol_table.get_symbol(name).is_some() => {
+                        class_usage.entry(name.to_string()).or_default()
+                    }
+                    None => continue,
+                };
+                if access.write {
+                    usage.written = true;
+                    assigned_or_reported.insert(name);
+                } else {
+                    usage.read = true;
+                }
+            }
+
+            for name in subroutine.locals.iter().flat_map(|locals| &locals.names) {
+                let usage = local_usage.get(name.name.as_str());
+                if let Some(problem) = usage.map_or(Some("is never used"), Usage::problem) {
+                    self.diagnostics.push(Diagnostic::warning(
+                        name.span,
+                        format!("'{}' {}", name.name, problem),
+                    ));
+                }
+            }
+        }
+
+        for variables in &class.variables {
+            for name in &variables.names {
+                let usage = class_usage.get(&name.name);
+                if let Some(problem) = usage.map_or(Some("is never used"), Usage::problem) {
+                    self.diagnostics.push(Diagnostic::warning(
+                        name.span,
+                        format!("the {} '{}' {}", variables.kind, name.name, problem),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::compiler::parser::Compiler;
+
+    #[test]
+    fn test_lint_class() {
+        let source = "class Counter {
+    field int count, unused;
+    static int total;
+    constructor Counter new(int count) {
+        var int a, b, c;
+        var Array d;
+        let c = a + 1;
+        let total = c;
+        let d[0] = 1;
+        let b = 2;
+        return this;
+    }
+    method int get() { return count; }
+}";
+        let mut compiler = Compiler::new(PathBuf::from("Counter.jack"), false);
+        compiler.compile_source(source);
+
+        let warnings: Vec<String> = compiler
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                "2:22: warning: the field 'unused' is never used",
+                "3:16: warning: the static 'total' is assigned but never read",
+                "4:33: warning: the parameter 'count' hides the field with the same name",
+                "5:20: warning: 'b' is assigned but never read",
+                "7:17: warning: 'a' is read before it's assigned",
+                "9:13: warning: 'd' is read before it's assigned",
+            ]
+        );
+    }
+}
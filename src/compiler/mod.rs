@@ -1,12 +1,20 @@
+pub mod ast;
 pub mod branches;
 pub mod char_set;
 pub mod code_gen;
+pub mod control_flow;
+pub mod diagnostics;
 pub mod files;
 pub mod grammar;
 pub mod keywords;
+pub mod lints;
+pub mod os_api;
 pub mod parser;
+pub mod registry;
 pub mod run;
 pub mod statements;
 pub mod symbol_table;
 pub mod symbols;
 pub mod tokens;
+pub mod type_check;
+pub mod xml;
@@ -0,0 +1,111 @@
+use crate::compiler::ast::{SubroutineKind, Type};
+use crate::compiler::registry::Signature;
+
+/// The standard Jack OS API, one subroutine per line
+const OS_API: &str = "\
+function void Math.init()
+function int Math.abs(int x)
+function int Math.multiply(int x, int y)
+function int Math.divide(int x, int y)
+function int Math.min(int x, int y)
+function int Math.max(int x, int y)
+function int Math.sqrt(int x)
+constructor String String.new(int maxLength)
+method void String.dispose()
+method int String.length()
+method char String.charAt(int j)
+method void String.setCharAt(int j, char c)
+method String String.appendChar(char c)
+method void String.eraseLastChar()
+method int String.intValue()
+method void String.setInt(int val)
+function char String.backSpace()
+function char String.doubleQuote()
+function char String.newLine()
+function Array Array.new(int size)
+method void Array.dispose()
+function void Output.init()
+function void Output.moveCursor(int i, int j)
+function void Output.printChar(char c)
+function void Output.printString(String s)
+function void Output.printInt(int i)
+function void Output.println()
+function void Output.backSpace()
+function void Screen.init()
+function void Screen.clearScreen()
+function void Screen.setColor(boolean b)
+function void Screen.drawPixel(int x, int y)
+function void Screen.drawLine(int x1, int y1, int x2, int y2)
+function void Screen.drawRectangle(int x1, int y1, int x2, int y2)
+function void Screen.drawCircle(int x, int y, int r)
+function void Keyboard.init()
+function char Keyboard.keyPressed()
+function char Keyboard.readChar()
+function String Keyboard.readLine(String message)
+function int Keyboard.readInt(String message)
+function void Memory.init()
+function int Memory.peek(int address)
+function void Memory.poke(int address, int value)
+function Array Memory.alloc(int size)
+function void Memory.deAlloc(Array o)
+function void Sys.init()
+function void Sys.halt()
+function void Sys.error(int errorCode)
+function void Sys.wait(int duration)";
+
+/// Every OS subroutine as (class name, subroutine name, signature)
+pub fn os_signatures() -> Vec<(String, String, Signature)> {
+    OS_API
+        .lines()
+        .map(|line| {
+            let (declaration, parameters) = line
+                .trim_end_matches(')')
+                .split_once('(')
+                .expect("Every OS subroutine has a parameter list");
+            let words: Vec<&str> = declaration.split_whitespace().collect();
+            let [kind, return_type, full_name] = words[..] else {
+                panic!("Couldn't read the OS subroutine '{}'", line);
+            };
+            let kind = match kind {
+                "constructor" => SubroutineKind::Constructor,
+                "function" => SubroutineKind::Function,
+                _ => SubroutineKind::Method,
+            };
+            let (class_name, name) = full_name
+                .split_once('.')
+                .expect("Every OS subroutine has its class");
+            let parameters = parameters
+                .split(", ")
+                .filter_map(|parameter| parameter.split_whitespace().next())
+                .map(Type::from_name)
+                .collect();
+
+            let signature = Signature {
+                kind,
+                return_type: Type::from_name(return_type),
+                parameters,
+            };
+            (class_name.to_string(), name.to_string(), signature)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_os_signatures() {
+        let signatures = os_signatures();
+        assert_eq!(signatures.len(), 49);
+        let (class_name, name, signature) = signatures
+            .iter()
+            .find(|(_, name, _)| name == "drawRectangle")
+            .unwrap();
+        assert_eq!(class_name, "Screen");
+        assert_eq!(name, "drawRectangle");
+        assert_eq!(signature.kind, SubroutineKind::Function);
+        assert_eq!(signature.parameters, vec![Type::Int; 4]);
+        assert_eq!(signature.return_type, Type::Void);
+    }
+}
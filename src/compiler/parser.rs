@@ -1,32 +1,65 @@
 use std::collections::HashMap;
+use std::fs;
 use std::fs::File;
-use std::io::{self, prelude::*, BufRead};
+use std::io::prelude::*;
 use std::path::PathBuf;
 
+use clap::ValueEnum;
+
+use crate::compiler::ast::{Class, SubroutineKind};
 use crate::compiler::branches::Branches;
 use crate::compiler::char_set::create_character_set;
+use crate::compiler::diagnostics::Diagnostic;
 use crate::compiler::keywords::make_keywords_array;
+use crate::compiler::registry::ClassRegistry;
 use crate::compiler::symbol_table::SymbolTable;
 use crate::compiler::symbols::{funky_symbols, make_symbols_array};
 use crate::compiler::tokens::Token;
+use crate::compiler::type_check::Strictness;
+use crate::compiler::xml::XmlWriter;
+
+/// The files the compiler can write for each class
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Emit {
+    /// `NameT.xml`, the tokens
+    Tokens,
+    /// `Name.xml`, the parse tree
+    Xml,
+    /// `Name.vm`
+    Vm,
+    /// `Name.ast.json`, the syntax tree with where everything is in the source
+    AstJson,
+}
 
 #[derive(Clone)]
 pub struct Compiler {
     pub file_path: PathBuf,
     pub debug: bool,
     pub tokens: Vec<Token>,
-    pub multi_line_comment: bool,
     pub symbols_list: [String; 23],
     pub funky_symbols: HashMap<String, String>,
     pub keywords_list: [String; 21],
     pub character_set: HashMap<String, u16>,
     pub output: String,
-    pub output_padding: usize,
     pub code: String,
     pub class_type: String,
+    /// Whether the subroutine being compiled is a function, method or constructor
+    pub subroutine_kind: SubroutineKind,
     pub class_symbol_table: SymbolTable,
     pub subroutine_symbol_table: SymbolTable,
     pub branches: Branches,
+    /// Emit the extended `mul`/`div` vm commands instead of calling Math
+    pub native_math: bool,
+    /// The whole .jack file, kept for showing the lines diagnostics point at
+    pub source: String,
+    pub diagnostics: Vec<Diagnostic>,
+    pub strictness: Strictness,
+    /// Warn about unused, unassigned and shadowed variables
+    pub lints: bool,
+    /// The syntax tree, once the file's parsed
+    pub class: Option<Class>,
+    /// Where to write the outputs instead of next to the .jack file
+    pub out_dir: Option<PathBuf>,
 }
 
 impl Compiler {
@@ -35,23 +68,29 @@ impl Compiler {
             file_path,
             debug,
             tokens: vec![],
-            multi_line_comment: false,
             symbols_list: make_symbols_array(),
             funky_symbols: funky_symbols(),
             keywords_list: make_keywords_array(),
             character_set: create_character_set(),
             output: String::new(),
-            output_padding: 0,
             code: String::new(),
             class_type: String::new(),
+            subroutine_kind: SubroutineKind::Function,
             class_symbol_table: SymbolTable::new(),
             subroutine_symbol_table: SymbolTable::new(),
             branches: Branches::new(),
+            native_math: false,
+            source: String::new(),
+            diagnostics: vec![],
+            strictness: Strictness::Lenient,
+            lints: true,
+            class: None,
+            out_dir: None,
         }
     }
 
     pub fn save_tokens(&mut self) {
-        let output_path = self.create_output_path("Test", "xml");
+        let output_path = self.create_output_path("T", "xml");
         let mut output_file = File::create(output_path).unwrap();
 
         output_file
@@ -69,7 +108,7 @@ impl Compiler {
     }
 
     pub fn save_grammar_output(&mut self) {
-        let output_path = self.create_output_path("Grammar", "xml");
+        let output_path = self.create_output_path("", "xml");
         let mut output_file = File::create(output_path).unwrap();
 
         output_file.write_all(self.output.as_bytes()).unwrap();
@@ -82,36 +121,154 @@ impl Compiler {
         output_file.write_all(self.code.as_bytes()).unwrap();
     }
 
+    pub fn save_ast_json(&mut self) {
+        let output_path = self.create_output_path("", "ast.json");
+        let json = serde_json::to_string_pretty(&self.class).unwrap();
+        fs::write(output_path, json).unwrap();
+    }
+
     fn create_output_path(&mut self, suffix: &str, extension: &str) -> PathBuf {
-        let mut output_file = self.file_path.clone();
-        let file_stem = output_file.file_stem().unwrap();
+        let file_stem = self.file_path.file_stem().unwrap();
         let asm_file = format!("{}{}.{}", file_stem.to_str().unwrap(), suffix, extension);
-        output_file.pop();
+        // next to the .jack file unless told otherwise
+        let mut output_file = match &self.out_dir {
+            Some(out_dir) => out_dir.clone(),
+            None => {
+                let mut folder = self.file_path.clone();
+                folder.pop();
+                folder
+            }
+        };
         output_file.push(asm_file);
 
         output_file
     }
 
-    pub fn compile_file(&mut self) {
+    /// Reads and parses the file, anything wrong with it ends up in `diagnostics`
+    pub fn parse_file(&mut self) {
         // read in text
+        self.source = fs::read_to_string(&self.file_path)
+            .expect("At this point we should know we have a .jack file");
+        let source = self.source.clone();
+        self.parse_source(&source);
+    }
+
+    /// Tokenizes the source and builds its syntax tree
+    pub fn parse_source(&mut self, source: &str) {
         // break out into tokens
-        self.tokenize_file();
-        self.parse_tokens_to_grammar();
-        self.save_tokens();
-        self.save_grammar_output();
-        self.save_vm_code();
+        self.tokenize(source);
+        // a broken token would only lead to more confusing errors further on
+        if !self.diagnostics.is_empty() {
+            return;
+        }
+        match self.parse_class() {
+            // statements with errors were left out of the class, the rest can still be checked
+            Ok(class) => self.class = Some(class),
+            Err(diagnostic) => self.diagnostics.push(diagnostic),
+        }
+    }
+
+    /// Checks the parsed class against every class compiled with it, then writes its
+    /// xml and vm code
+    pub fn compile_class(&mut self, registry: &ClassRegistry) {
+        if let Some(class) = self.class.clone() {
+            self.check_class(&class, registry);
+            self.check_control_flow(&class);
+            if self.lints {
+                self.lint_class(&class);
+            }
+            let mut xml = XmlWriter::default();
+            xml.class(&class);
+            self.output = xml.output;
+            self.generate_class(&class);
+        }
+        self.diagnostics
+            .sort_by_key(|diagnostic| (diagnostic.span.line, diagnostic.span.column));
     }
 
-    fn tokenize_file(&mut self) {
-        let input_contents =
-            File::open(&self.file_path).expect("At this point we should know we have a .jack file");
+    /// Compiles a single class on its own, calls to other classes can't be checked
+    #[cfg(test)]
+    pub fn compile_source(&mut self, source: &str) {
+        self.parse_source(source);
+        let mut registry = ClassRegistry::new();
+        if let Some(class) = &self.class {
+            registry.add_class(class);
+        }
+        self.compile_class(&registry);
+    }
 
-        // first round of tokenizing
-        for line in io::BufReader::new(input_contents)
-            .lines()
-            .map_while(Result::ok)
-        {
-            self.tokenize(&line);
+    /// Writes the chosen outputs, only once the file compiled without errors
+    pub fn save_outputs(&mut self, emit: &[Emit]) {
+        for output in emit {
+            match output {
+                Emit::Tokens => self.save_tokens(),
+                Emit::Xml => self.save_grammar_output(),
+                Emit::Vm => self.save_vm_code(),
+                Emit::AstJson => self.save_ast_json(),
+            }
         }
     }
+
+    /// Every diagnostic with the source line it points at
+    pub fn render_diagnostics(&self) -> String {
+        self.diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.render(&self.file_path, &self.source))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{Compiler, Emit};
+
+    #[test]
+    fn test_save_outputs() {
+        let directory = env::temp_dir().join(format!("n2ttools_emit_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let source_dir = directory.join("src");
+        let out_dir = directory.join("build");
+        fs::create_dir_all(&source_dir).unwrap();
+        fs::create_dir_all(&out_dir).unwrap();
+        let source = "class Main {
+    function void main() {
+        return;
+    }
+}";
+        fs::write(source_dir.join("Main.jack"), source).unwrap();
+
+        let mut compiler = Compiler::new(source_dir.join("Main.jack"), false);
+        compiler.out_dir = Some(out_dir.clone());
+        compiler.compile_source(source);
+        assert!(compiler.diagnostics.is_empty());
+        compiler.save_outputs(&[Emit::Tokens, Emit::Xml, Emit::AstJson]);
+
+        let names = |folder: &PathBuf| {
+            let mut names: Vec<String> = fs::read_dir(folder)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&out_dir), ["Main.ast.json", "Main.xml", "MainT.xml"]);
+        // nothing is written next to the source
+        assert_eq!(names(&source_dir), ["Main.jack"]);
+
+        let tokens = fs::read_to_string(out_dir.join("MainT.xml")).unwrap();
+        assert!(tokens.starts_with("<tokens>\n<keyword> class </keyword>\n"));
+        let xml = fs::read_to_string(out_dir.join("Main.xml")).unwrap();
+        assert!(xml.starts_with("<class>\n"));
+        let json = fs::read_to_string(out_dir.join("Main.ast.json")).unwrap();
+        let ast: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(ast["name"]["name"], "Main");
+        assert_eq!(ast["subroutines"][0]["name"]["name"], "main");
+        assert_eq!(ast["subroutines"][0]["name"]["span"]["line"], 2);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
 }
@@ -0,0 +1,239 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::compiler::ast::{Class, SubroutineCall, SubroutineKind, Type};
+use crate::compiler::os_api::os_signatures;
+use crate::compiler::parser::Compiler;
+use crate::compiler::symbol_table::Symbol;
+
+/// What a caller needs to know about a subroutine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub kind: SubroutineKind,
+    pub return_type: Type,
+    pub parameters: Vec<Type>,
+}
+
+/// Every class being compiled together and the signatures of their subroutines,
+/// so calls from one file into another can be checked
+#[derive(Debug, Clone)]
+pub struct ClassRegistry {
+    pub classes: BTreeMap<String, BTreeMap<String, Signature>>,
+    /// Classes whose files couldn't be parsed, calls into them aren't checked
+    pub unparsed: BTreeSet<String>,
+}
+
+impl ClassRegistry {
+    /// Starts with the bundled Jack OS API, a class added with the same name as an OS
+    /// class replaces it
+    pub fn new() -> Self {
+        let mut classes: BTreeMap<String, BTreeMap<String, Signature>> = BTreeMap::new();
+        for (class_name, name, signature) in os_signatures() {
+            classes
+                .entry(class_name)
+                .or_default()
+                .insert(name, signature);
+        }
+        ClassRegistry {
+            classes,
+            unparsed: BTreeSet::new(),
+        }
+    }
+
+    /// Adds the compiler's class, or just its name when the file didn't parse, since its
+    /// own errors already say what's wrong and every call into it would only add more
+    pub fn add_compiler(&mut self, compiler: &Compiler) {
+        match &compiler.class {
+            Some(class) => self.add_class(class),
+            None => {
+                if let Some(name) = compiler.file_path.file_stem() {
+                    self.unparsed.insert(name.to_string_lossy().to_string());
+                }
+            }
+        }
+    }
+
+    pub fn add_class(&mut self, class: &Class) {
+        let subroutines = class
+            .subroutines
+            .iter()
+            .map(|subroutine| {
+                let signature = Signature {
+                    kind: subroutine.kind,
+                    return_type: subroutine.return_type.clone(),
+                    parameters: subroutine
+                        .parameters
+                        .iter()
+                        .map(|parameter| parameter.var_type.clone())
+                        .collect(),
+                };
+                (subroutine.name.name.clone(), signature)
+            })
+            .collect();
+        self.classes.insert(class.name.name.clone(), subroutines);
+    }
+
+    pub fn is_unparsed(&self, class_name: &str) -> bool {
+        self.unparsed.contains(class_name)
+    }
+
+    pub fn has_class(&self, class_name: &str) -> bool {
+        self.classes.contains_key(class_name)
+    }
+
+    pub fn signature(&self, class_name: &str, subroutine_name: &str) -> Option<&Signature> {
+        self.classes.get(class_name)?.get(subroutine_name)
+    }
+}
+
+/// What a subroutine is called on
+#[derive(Debug, Clone)]
+pub enum CallTarget {
+    /// `f()`, a method of the current object
+    This,
+    /// `variable.m()`, a method of the object in the variable
+    Variable(Symbol),
+    /// `ClassName.f()`, a function or constructor
+    Class,
+}
+
+impl Compiler {
+    /// The class a call goes to and what it's called on. Like in Jack itself, a name
+    /// before the `.` is a variable if one's declared, otherwise it's a class
+    pub fn resolve_call(&mut self, call: &SubroutineCall) -> (String, CallTarget) {
+        match &call.receiver {
+            None => (self.class_type.clone(), CallTarget::This),
+            Some(receiver) => match self.get_symbol(&receiver.name).cloned() {
+                Some(symbol) => (symbol.var_type.clone(), CallTarget::Variable(symbol)),
+                None => (receiver.name.clone(), CallTarget::Class),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::ClassRegistry;
+    use crate::compiler::parser::Compiler;
+
+    #[test]
+    fn test_check_calls() {
+        let sources = [
+            (
+                "Point.jack",
+                "class Point {
+    field int x;
+    constructor Point new(int ax) { let x = ax; return this; }
+    method int getX() { return x; }
+    function int origin() { return 0; }
+}",
+            ),
+            (
+                "Main.jack",
+                "class Main {
+    function void main() {
+        var Point p;
+        let p = Point.new(1, 2);
+        do p.getX();
+        do p.origin();
+        do Point.getX();
+        do Point.move();
+        do Pont.new(1);
+        do Output.printInt(p.getX());
+        do Screen.drawPixel(1);
+        return;
+    }
+}",
+            ),
+        ];
+        let mut compilers: Vec<Compiler> = sources
+            .iter()
+            .map(|(file, source)| {
+                let mut compiler = Compiler::new(PathBuf::from(file), false);
+                compiler.parse_source(source);
+                compiler
+            })
+            .collect();
+        let mut registry = ClassRegistry::new();
+        for compiler in &compilers {
+            registry.add_class(compiler.class.as_ref().unwrap());
+        }
+        for compiler in &mut compilers {
+            compiler.compile_class(&registry);
+        }
+
+        assert!(compilers[0].diagnostics.is_empty());
+        let errors: Vec<String> = compilers[1]
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "4:23: 'Point.new' takes 1 argument but is given 2",
+                "6:14: 'Point.origin' is a function, so it's called as Point.origin() rather than on a variable",
+                "7:18: 'Point.getX' is a method, so it has to be called on a Point object",
+                "8:18: 'Point' has no subroutine 'move'",
+                "9:12: 'Pont' isn't a class or a declared variable",
+                "11:19: 'Screen.drawPixel' takes 2 arguments but is given 1",
+            ]
+        );
+        assert!(
+            compilers[1]
+                .code
+                .contains("push local 0\ncall Point.getX 1\n")
+        );
+    }
+
+    #[test]
+    fn test_unparsed_classes() {
+        let sources = [
+            (
+                "Square.jack",
+                "class Square {\n    method void draw() { return }\n}",
+            ),
+            (
+                "Main.jack",
+                "class Main {
+    function void main() {
+        var Square s;
+        let s = Square.new();
+        do s.draw();
+        do Circle.new();
+        return;
+    }
+}",
+            ),
+        ];
+        let mut compilers: Vec<Compiler> = sources
+            .iter()
+            .map(|(file, source)| {
+                let mut compiler = Compiler::new(PathBuf::from(file), false);
+                compiler.parse_source(source);
+                compiler
+            })
+            .collect();
+        let mut registry = ClassRegistry::new();
+        for compiler in &compilers {
+            registry.add_compiler(compiler);
+        }
+        for compiler in &mut compilers {
+            compiler.compile_class(&registry);
+        }
+
+        assert!(compilers[0].class.is_none());
+        assert!(!compilers[0].diagnostics.is_empty());
+        // only the class that doesn't exist at all is reported
+        let errors: Vec<String> = compilers[1]
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            ["6:12: 'Circle' isn't a class or a declared variable"]
+        );
+    }
+}
@@ -1,9 +1,28 @@
+use std::fs;
 use std::path::PathBuf;
+use std::process;
 
-use crate::compiler::files::valid_files;
-use crate::compiler::parser::Compiler;
+use crate::compiler::files::{sibling_files, valid_files};
+use crate::compiler::parser::{Compiler, Emit};
+use crate::compiler::registry::ClassRegistry;
+use crate::compiler::type_check::Strictness;
 
-pub fn run_compiler(path: String, debug: bool) {
+/// Everything that changes how the .jack files get compiled and what gets written
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    /// Emit the extended `mul`/`div` vm commands instead of calling Math
+    pub native_math: bool,
+    pub strictness: Strictness,
+    /// Warn about unused, unassigned and shadowed variables
+    pub lints: bool,
+    /// Folder of the user's own OS, checked against instead of the bundled signatures
+    pub os_source: Option<PathBuf>,
+    pub emit: Vec<Emit>,
+    /// Where to write the outputs instead of next to each .jack file
+    pub out_dir: Option<PathBuf>,
+}
+
+pub fn run_compiler(path: String, options: CompileOptions, debug: bool) {
     println!("Running the compiler on '{}'", path);
     let path_buf = PathBuf::from(path);
 
@@ -13,11 +32,145 @@ pub fn run_compiler(path: String, debug: bool) {
         return;
     }
 
+    let mut compilers = vec![];
     for file in files.expect("Should have something after .is_none() check") {
         if debug {
             println!("Working on file {}", file.display());
         }
         let mut compiler = Compiler::new(file, debug);
-        compiler.compile_file();
+        compiler.native_math = options.native_math;
+        compiler.strictness = options.strictness;
+        compiler.lints = options.lints;
+        compiler.out_dir = options.out_dir.clone();
+        compiler.parse_file();
+        compilers.push(compiler);
+    }
+
+    // the user's own OS is only read for its signatures, which replace the bundled ones
+    let mut os_compilers = vec![];
+    if let Some(os_source) = &options.os_source {
+        let Some(os_files) = valid_files(os_source) else {
+            println!("Could not find any valid '.jack' files in the OS source.");
+            return;
+        };
+        for file in os_files {
+            let mut compiler = Compiler::new(file, debug);
+            compiler.parse_file();
+            os_compilers.push(compiler);
+        }
+    }
+
+    // a single file can still call the classes next to it, which are only read for their
+    // signatures and aren't compiled or reported on
+    let mut sibling_compilers = vec![];
+    if path_buf.is_file() {
+        for file in sibling_files(&path_buf) {
+            let mut compiler = Compiler::new(file, debug);
+            compiler.parse_file();
+            sibling_compilers.push(compiler);
+        }
+    }
+
+    // every class is known before any of them are checked, so calls between files can be
+    let mut registry = ClassRegistry::new();
+    for compiler in os_compilers
+        .iter()
+        .chain(&sibling_compilers)
+        .chain(&compilers)
+    {
+        registry.add_compiler(compiler);
+    }
+    for compiler in &mut compilers {
+        compiler.compile_class(&registry);
+    }
+
+    // warnings are shown either way, but nothing gets written unless every file compiled
+    for compiler in os_compilers.iter().chain(&compilers) {
+        print!("{}", compiler.render_diagnostics());
+    }
+    let (errors, warnings): (Vec<_>, Vec<_>) = os_compilers
+        .iter()
+        .chain(&compilers)
+        .flat_map(|compiler| &compiler.diagnostics)
+        .partition(|diagnostic| diagnostic.is_error());
+    if !errors.is_empty() {
+        println!(
+            "Found {} error{}, no files were written.",
+            errors.len(),
+            if errors.len() == 1 { "" } else { "s" }
+        );
+        process::exit(1);
+    }
+    if !warnings.is_empty() {
+        println!(
+            "Found {} warning{}.",
+            warnings.len(),
+            if warnings.len() == 1 { "" } else { "s" }
+        );
+    }
+    if let Some(out_dir) = &options.out_dir {
+        fs::create_dir_all(out_dir).expect("Couldn't create the output folder");
+    }
+    for compiler in &mut compilers {
+        compiler.save_outputs(&options.emit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::{CompileOptions, run_compiler};
+    use crate::compiler::parser::Emit;
+    use crate::compiler::type_check::Strictness;
+
+    #[test]
+    fn test_compile_single_file() {
+        let directory = env::temp_dir().join(format!("n2ttools_single_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("Main.jack"),
+            "class Main {
+    function void main() {
+        var Square s;
+        let s = Square.new(2);
+        do s.draw();
+        return;
+    }
+}",
+        )
+        .unwrap();
+        fs::write(
+            directory.join("Square.jack"),
+            "class Square {
+    field int size;
+    constructor Square new(int a) { let size = a; return this; }
+    method void draw() { return; }
+}",
+        )
+        .unwrap();
+
+        let options = CompileOptions {
+            native_math: false,
+            strictness: Strictness::Lenient,
+            lints: true,
+            os_source: None,
+            emit: vec![Emit::Vm],
+            out_dir: None,
+        };
+        run_compiler(
+            directory.join("Main.jack").display().to_string(),
+            options,
+            false,
+        );
+
+        let vm = fs::read_to_string(directory.join("Main.vm")).unwrap();
+        assert!(vm.contains("call Square.new 1\n"));
+        assert!(vm.contains("call Square.draw 1\n"));
+        // Square is only read for its signatures
+        assert!(!directory.join("Square.vm").exists());
+        fs::remove_dir_all(&directory).unwrap();
     }
 }
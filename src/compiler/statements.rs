@@ -1,477 +1,370 @@
 use std::iter::Peekable;
 
+use crate::compiler::ast::{
+    DoStatement, Expression, Identifier, IfStatement, KeywordConstant, LetStatement, Op,
+    ReturnStatement, Statement, SubroutineCall, Term, TermKind, UnaryOp, WhileStatement,
+};
+use crate::compiler::diagnostics::Diagnostic;
 use crate::compiler::parser::Compiler;
 use crate::compiler::tokens::Token;
 
 use super::tokens::TokenType;
 
 impl Compiler {
-    pub fn process_statements<'a, I: Iterator<Item = &'a Token>>(
+    pub fn parse_statements<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-    ) {
-        self.save_to_output("<statements>");
-        self.output_padding += 2;
-
-        let mut next_token = tokens_iter
-            .peek()
-            .expect("Should not be at end of tokens yet");
-
+    ) -> Result<Vec<Statement>, Diagnostic> {
+        let mut statements = vec![];
         loop {
-            match next_token.token_str.as_str() {
-                "let" => self.process_let_statement(tokens_iter),
-                "if" => self.process_if_statement(tokens_iter),
-                "while" => self.process_while_statement(tokens_iter),
-                "do" => self.process_do_statement(tokens_iter),
-                "return" => self.process_return_statement(tokens_iter),
-                _ => break,
+            let next_token = self.peek_token(tokens_iter)?;
+            let result = match next_token.token_str.as_str() {
+                "let" => self.parse_let_statement(tokens_iter).map(Statement::Let),
+                "if" => self.parse_if_statement(tokens_iter).map(Statement::If),
+                "while" => self
+                    .parse_while_statement(tokens_iter)
+                    .map(Statement::While),
+                "do" => self.parse_do_statement(tokens_iter).map(Statement::Do),
+                "return" => self
+                    .parse_return_statement(tokens_iter)
+                    .map(Statement::Return),
+                _ => return Ok(statements),
+            };
+            match result {
+                Ok(statement) => statements.push(statement),
+                // note the error and carry on from the next statement to find any others
+                Err(diagnostic) => {
+                    self.diagnostics.push(diagnostic);
+                    self.skip_statement(tokens_iter);
+                }
             }
-            next_token = tokens_iter
-                .peek()
-                .expect("Should not be at the end of tokens yet - loop")
         }
+    }
 
-        self.output_padding -= 2;
-        self.save_to_output("</statements>");
+    /// Skips what's left of a broken statement, up to and including its `;`, or up to
+    /// the next statement or the `}` closing the block it's in. Blocks inside it are skipped whole
+    fn skip_statement<'a, I: Iterator<Item = &'a Token>>(&self, tokens_iter: &mut Peekable<I>) {
+        let mut depth = 0;
+        while let Some(token) = tokens_iter.peek() {
+            match token.token_str.as_str() {
+                "let" | "if" | "while" | "do" | "return" if depth == 0 => return,
+                "}" if depth == 0 => return,
+                ";" if depth == 0 => {
+                    tokens_iter.next();
+                    return;
+                }
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    tokens_iter.next();
+                    // an if's block is followed by its else, which belongs to the same statement
+                    let else_follows = tokens_iter
+                        .peek()
+                        .is_some_and(|token| token.token_str == "else");
+                    if depth == 0 && !else_follows {
+                        return;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            tokens_iter.next();
+        }
     }
 
-    pub fn process_let_statement<'a, I: Iterator<Item = &'a Token>>(
+    fn parse_let_statement<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-    ) {
-        self.save_to_output("<letStatement>");
-        self.output_padding += 2;
-
+    ) -> Result<LetStatement, Diagnostic> {
         // should be a let keyword
-        self.process_specific(tokens_iter, String::from("let"), TokenType::Keyword);
+        let keyword = self.expect(tokens_iter, "let", TokenType::Keyword)?;
         // should be var name
-        let var_name = self.process_type(tokens_iter, TokenType::Identifier);
-
-        let var_name_symbol = self.get_symbol(&var_name);
-        let symbol = match var_name_symbol {
-            Some(symbol) => symbol.clone(),
-            None => panic!("Could not find {} in our symbol table", var_name),
-        };
+        let name = self.expect_identifier(tokens_iter)?;
 
         // need to check for an expression here if we see a '['
-        let next_token = tokens_iter.next().unwrap();
-        let mut array_handling = false;
-        if next_token.token_str == "[" {
-            array_handling = true;
-            self.save_to_output("<symbol> [ </symbol>");
-
-            // parse expression
-            self.process_expression(tokens_iter);
-            // push array in
-            self.push_symbol(&symbol);
-            self.write_code("add");
-
-            self.process_specific(tokens_iter, String::from("]"), TokenType::Symbol);
-            self.process_specific(tokens_iter, String::from("="), TokenType::Symbol);
-        } else if next_token.token_str == "=" {
-            // should be =
-            self.save_to_output("<symbol> = </symbol>");
-        } else {
-            panic!("There should only be [ or = here {}", next_token);
-        }
-
-        // parse expression
-        self.process_expression(tokens_iter);
-
-        if array_handling {
-            self.write_code("pop temp 0");
-            self.write_code("pop pointer 1");
-            self.write_code("push temp 0");
-            self.write_code("pop that 0");
-        } else {
-            // now pop our var
-            self.pop_symbol(&symbol);
-        }
-
-        // parse ;
-        self.process_specific(tokens_iter, String::from(";"), TokenType::Symbol);
-
-        self.output_padding -= 2;
-        self.save_to_output("</letStatement>");
+        let next_token = self.peek_token(tokens_iter)?;
+        let index = match next_token.token_str.as_str() {
+            "[" => {
+                tokens_iter.next();
+                let index = self.parse_expression(tokens_iter)?;
+                self.expect(tokens_iter, "]", TokenType::Symbol)?;
+                Some(index)
+            }
+            "=" => None,
+            _ => {
+                return Err(self.error(
+                    next_token,
+                    &format!(
+                        "expected '[' or '=' but found '{}'",
+                        next_token.source_text()
+                    ),
+                ));
+            }
+        };
+        self.expect(tokens_iter, "=", TokenType::Symbol)?;
+        let value = self.parse_expression(tokens_iter)?;
+        self.expect(tokens_iter, ";", TokenType::Symbol)?;
+
+        Ok(LetStatement {
+            name,
+            index,
+            value,
+            span: keyword.span,
+        })
     }
 
-    pub fn process_if_statement<'a, I: Iterator<Item = &'a Token>>(
+    fn parse_if_statement<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-    ) {
-        // keep a static counter here for this if loop
-        let current_if_counter = self.branches.if_counter;
-        self.branches.if_counter += 1;
-
-        self.save_to_output("<ifStatement>");
-        self.output_padding += 2;
-
-        // should be a if keyword
-        self.process_specific(tokens_iter, String::from("if"), TokenType::Keyword);
-        self.process_specific(tokens_iter, String::from("("), TokenType::Symbol);
-
-        self.process_expression(tokens_iter);
-        self.write_code(&format!("if-goto IF_TRUE{}", current_if_counter));
-        self.write_code(&format!("goto IF_FALSE{}", current_if_counter));
-
-        self.process_specific(tokens_iter, String::from(")"), TokenType::Symbol);
-        self.process_specific(tokens_iter, String::from("{"), TokenType::Symbol);
-
-        self.write_code(&format!("label IF_TRUE{}", current_if_counter));
-        self.process_statements(tokens_iter);
-        self.process_specific(tokens_iter, String::from("}"), TokenType::Symbol);
+    ) -> Result<IfStatement, Diagnostic> {
+        let keyword = self.expect(tokens_iter, "if", TokenType::Keyword)?;
+        self.expect(tokens_iter, "(", TokenType::Symbol)?;
+        let condition = self.parse_expression(tokens_iter)?;
+        self.expect(tokens_iter, ")", TokenType::Symbol)?;
+        let then_statements = self.parse_block(tokens_iter)?;
 
         // now check for else
-        let else_peek = tokens_iter.peek().unwrap();
-        if else_peek.token_str == "else" {
-            self.write_code(&format!("goto IF_END{}", current_if_counter));
-            self.process_specific(tokens_iter, String::from("else"), TokenType::Keyword);
-            self.process_specific(tokens_iter, String::from("{"), TokenType::Symbol);
-            self.write_code(&format!("label IF_FALSE{}", current_if_counter));
-            self.process_statements(tokens_iter);
-            self.process_specific(tokens_iter, String::from("}"), TokenType::Symbol);
-            self.write_code(&format!("label IF_END{}", current_if_counter));
-        } else {
-            self.write_code(&format!("label IF_FALSE{}", current_if_counter));
+        let mut else_statements = None;
+        if self.peek_token(tokens_iter)?.token_str == "else" {
+            tokens_iter.next();
+            else_statements = Some(self.parse_block(tokens_iter)?);
         }
 
-        self.output_padding -= 2;
-        self.save_to_output("</ifStatement>");
+        Ok(IfStatement {
+            condition,
+            then_statements,
+            else_statements,
+            span: keyword.span,
+        })
     }
 
-    pub fn process_while_statement<'a, I: Iterator<Item = &'a Token>>(
+    fn parse_while_statement<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-    ) {
-        let current_while_counter = self.branches.while_counter;
-        self.branches.while_counter += 1;
-
-        self.save_to_output("<whileStatement>");
-        self.output_padding += 2;
-
-        // should be a while keyword
-        self.process_specific(tokens_iter, String::from("while"), TokenType::Keyword);
-        self.write_code(&format!("label WHILE_EXP{}", current_while_counter));
-
-        self.process_specific(tokens_iter, String::from("("), TokenType::Symbol);
-        self.process_expression(tokens_iter);
-        self.process_specific(tokens_iter, String::from(")"), TokenType::Symbol);
-        self.write_code("not");
-        self.write_code(&format!("if-goto WHILE_END{}", current_while_counter));
-
-        self.process_specific(tokens_iter, String::from("{"), TokenType::Symbol);
-        self.process_statements(tokens_iter);
-        self.process_specific(tokens_iter, String::from("}"), TokenType::Symbol);
-        self.write_code(&format!("goto WHILE_EXP{}", current_while_counter));
-
-        self.write_code(&format!("label WHILE_END{}", current_while_counter));
-
-        self.output_padding -= 2;
-        self.save_to_output("</whileStatement>");
+    ) -> Result<WhileStatement, Diagnostic> {
+        let keyword = self.expect(tokens_iter, "while", TokenType::Keyword)?;
+        self.expect(tokens_iter, "(", TokenType::Symbol)?;
+        let condition = self.parse_expression(tokens_iter)?;
+        self.expect(tokens_iter, ")", TokenType::Symbol)?;
+        let statements = self.parse_block(tokens_iter)?;
+
+        Ok(WhileStatement {
+            condition,
+            statements,
+            span: keyword.span,
+        })
     }
 
-    pub fn process_do_statement<'a, I: Iterator<Item = &'a Token>>(
+    /// `{ statements }`
+    fn parse_block<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-    ) {
-        self.save_to_output("<doStatement>");
-        self.output_padding += 2;
-
-        self.process_subroutinecall(tokens_iter);
-
-        self.output_padding -= 2;
-        self.save_to_output("</doStatement>");
+    ) -> Result<Vec<Statement>, Diagnostic> {
+        self.expect(tokens_iter, "{", TokenType::Symbol)?;
+        let statements = self.parse_statements(tokens_iter)?;
+        self.expect(tokens_iter, "}", TokenType::Symbol)?;
+        Ok(statements)
     }
 
-    pub fn process_return_statement<'a, I: Iterator<Item = &'a Token>>(
+    fn parse_do_statement<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-    ) {
-        self.save_to_output("<returnStatement>");
-        self.output_padding += 2;
+    ) -> Result<DoStatement, Diagnostic> {
+        let keyword = self.expect(tokens_iter, "do", TokenType::Keyword)?;
+        // class, var or subroutine Name
+        let name = self.expect_identifier(tokens_iter)?;
+        let call = self.parse_subroutine_call(tokens_iter, name)?;
+        self.expect(tokens_iter, ";", TokenType::Symbol)?;
+
+        Ok(DoStatement {
+            call,
+            span: keyword.span,
+        })
+    }
 
-        self.process_specific(tokens_iter, String::from("return"), TokenType::Keyword);
-        let peek = tokens_iter.peek().unwrap();
-        if peek.token_str != ";" {
-            self.process_expression(tokens_iter);
-        } else {
-            // if there are no expressions present
-            // we push constant 0
-            self.write_code("push constant 0");
+    fn parse_return_statement<'a, I: Iterator<Item = &'a Token>>(
+        &mut self,
+        tokens_iter: &mut Peekable<I>,
+    ) -> Result<ReturnStatement, Diagnostic> {
+        let keyword = self.expect(tokens_iter, "return", TokenType::Keyword)?;
+        let mut value = None;
+        if self.peek_token(tokens_iter)?.token_str != ";" {
+            value = Some(self.parse_expression(tokens_iter)?);
         }
-        self.process_specific(tokens_iter, String::from(";"), TokenType::Symbol);
-        self.compile_return();
+        self.expect(tokens_iter, ";", TokenType::Symbol)?;
 
-        self.output_padding -= 2;
-        self.save_to_output("</returnStatement>");
+        Ok(ReturnStatement {
+            value,
+            span: keyword.span,
+        })
     }
 
-    pub fn process_expression<'a, I: Iterator<Item = &'a Token>>(
+    pub fn parse_expression<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-    ) {
-        self.save_to_output("<expression>");
-        self.output_padding += 2;
-
-        // push term in
-        self.process_term(tokens_iter);
+    ) -> Result<Expression, Diagnostic> {
+        let term = self.parse_term(tokens_iter)?;
 
         // check for op and more terms
-        let ops = ["+", "-", "*", "/", "&amp;", "|", "&lt;", "&gt;", "="];
-        let mut op_peek = tokens_iter.peek().unwrap();
-        loop {
-            if !ops.contains(&op_peek.token_str.as_str()) {
-                break;
-            }
-            let operator = self.process_type(tokens_iter, TokenType::Symbol);
-            // push second term
-            self.process_term(tokens_iter);
-            // work operator
-            if ops.contains(&operator.as_str()) {
-                self.compile_math_operator(&operator);
-            }
-            op_peek = tokens_iter.peek().unwrap();
+        let mut operations = vec![];
+        while let Some(op) = Op::from_symbol(&self.peek_token(tokens_iter)?.token_str) {
+            tokens_iter.next();
+            operations.push((op, self.parse_term(tokens_iter)?));
         }
 
-        self.output_padding -= 2;
-        self.save_to_output("</expression>");
+        Ok(Expression { term, operations })
     }
 
-    pub fn process_term<'a, I: Iterator<Item = &'a Token>>(
+    pub fn parse_term<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-    ) {
-        // Need to deal with
-        // x IntegerConstant
-        // x StringConstant
-        // x keywordConstant
-        // varName
-        // varName[expression]
-        // x (expression)
-        // x unaryOp term
-        // subroutineCall
-
-        // get term
-        let next = tokens_iter.next().unwrap();
-        self.save_to_output("<term>");
-        self.output_padding += 2;
-
-        // IntegerConstant, StringConstant
-        // or Keyword constant (like this, true, null, or false)
-        if (next.token_type == TokenType::StringConstant)
-            | (next.token_type == TokenType::IntegerConstant)
-            | (next.token_type == TokenType::Keyword)
-        {
-            self.save_to_output(&next.to_string());
-            match next.token_type {
-                TokenType::IntegerConstant => self.compile_constant(&next.token_str),
-                TokenType::Keyword => self.compile_keyword(&next.token_str),
-                TokenType::StringConstant => self.compile_string(&next.token_str),
-                _ => panic!("Shouldn't see any other token type here: '{}'", next),
+    ) -> Result<Term, Diagnostic> {
+        let next = self.next_token(tokens_iter)?;
+        let span = next.span;
+        let kind = match (&next.token_type, next.token_str.as_str()) {
+            (TokenType::IntegerConstant, constant) => {
+                TermKind::IntegerConstant(constant.to_string())
             }
-        }
-
-        // for handling our expression if it's wrapped in parenthesis
-        // (expression)
-        if next.token_str == "(" {
-            self.save_to_output("<symbol> ( </symbol>");
-            self.process_expression(tokens_iter);
-            self.process_specific(tokens_iter, String::from(")"), TokenType::Symbol);
-        }
-
-        // unaryOp term
-        if (next.token_str == "-") | (next.token_str == "~") {
-            self.save_to_output(&next.to_string());
-            self.process_term(tokens_iter);
-            self.compile_unary_op(&next.token_str);
-        }
-
-        // if we get an identifier, we have to see if it becomes either
-        // varName
-        // varName[expression]
-        // or subroutineCall
-        if next.token_type == TokenType::Identifier {
-            self.save_to_output(&next.to_string());
-
-            let mut name = next.token_str.clone();
-            let og_name = name.clone();
-            let mut expression_count = 0;
-            let mut method_call = false;
-
-            let symbol_check = self.get_symbol(&og_name);
-            if let Some(symbol) = symbol_check {
-                expression_count += 1;
-                name = symbol.var_type.clone();
-                method_call = true;
+            (TokenType::StringConstant, string) => TermKind::StringConstant(string.to_string()),
+            (TokenType::Keyword, "true") => TermKind::Keyword(KeywordConstant::True),
+            (TokenType::Keyword, "false") => TermKind::Keyword(KeywordConstant::False),
+            (TokenType::Keyword, "null") => TermKind::Keyword(KeywordConstant::Null),
+            (TokenType::Keyword, "this") => TermKind::Keyword(KeywordConstant::This),
+            // (expression)
+            (TokenType::Symbol, "(") => {
+                let expression = self.parse_expression(tokens_iter)?;
+                self.expect(tokens_iter, ")", TokenType::Symbol)?;
+                TermKind::Parenthesized(Box::new(expression))
             }
-
-            let peek = tokens_iter.peek().unwrap();
-            match peek.token_str.as_str() {
-                "[" => {
-                    // dealing with an array
-                    // let x = arr[2]
-                    // where arr is local 0
-                    // and x is local 1
-                    //
-                    // array part
-                    // push local 0
-                    // push constant 2
-                    // add
-                    // pop pointer 1
-                    //
-                    // x part
-                    // push that 0
-                    // pop local 1
-                    //
-                    // let arr[expression1] = expression2
-                    // push local 0 (arr)
-                    // compile expresssion1
-                    // add
-                    // pop pointer 1
-                    // compile expression2
-                    // pop temp 0
-                    // pop pointer 1
-                    // push temp 0
-                    // pop that 0
-                    let symbol = self.get_symbol(&og_name).unwrap().clone();
-
-                    self.process_specific(tokens_iter, String::from("["), TokenType::Symbol);
-                    self.process_expression(tokens_iter);
-                    self.process_specific(tokens_iter, String::from("]"), TokenType::Symbol);
-
-                    self.push_symbol(&symbol);
-                    self.write_code("add");
-
-                    // unsure
-                    self.write_code("pop pointer 1");
-                    self.write_code("push that 0");
-                }
-                "(" => {
-                    // if using a method of the current object, need to push in this
-                    self.write_code("push pointer 0");
-
-                    self.process_specific(tokens_iter, String::from("("), TokenType::Symbol);
-                    name = format!("{}.{}", self.class_type, name);
-                    expression_count += self.process_expression_list(tokens_iter);
-                    expression_count += 1; // implies we are using a method of the current object
-                    self.process_specific(tokens_iter, String::from(")"), TokenType::Symbol);
-
-                    self.write_code(&format!("call {} {}", name, expression_count));
-                }
-                "." => {
-                    if method_call {
-                        // symbol table shouldn't have changed by this point
-                        let symbol = self.get_symbol(&og_name).unwrap().clone();
-                        self.push_symbol(&symbol);
+            // unaryOp term
+            (TokenType::Symbol, "-") => {
+                TermKind::Unary(UnaryOp::Neg, Box::new(self.parse_term(tokens_iter)?))
+            }
+            (TokenType::Symbol, "~") => {
+                TermKind::Unary(UnaryOp::Not, Box::new(self.parse_term(tokens_iter)?))
+            }
+            // varName, varName[expression] or subroutineCall
+            (TokenType::Identifier, name) => {
+                let name = Identifier {
+                    name: name.to_string(),
+                    span,
+                };
+                match self.peek_token(tokens_iter)?.token_str.as_str() {
+                    "[" => {
+                        tokens_iter.next();
+                        let index = self.parse_expression(tokens_iter)?;
+                        self.expect(tokens_iter, "]", TokenType::Symbol)?;
+                        TermKind::ArrayAccess(name, Box::new(index))
                     }
-
-                    name +=
-                        &self.process_specific(tokens_iter, String::from("."), TokenType::Symbol);
-                    name += &self.process_type(tokens_iter, TokenType::Identifier);
-                    self.process_specific(tokens_iter, String::from("("), TokenType::Symbol);
-                    expression_count += self.process_expression_list(tokens_iter);
-                    self.process_specific(tokens_iter, String::from(")"), TokenType::Symbol);
-
-                    self.write_code(&format!("call {} {}", name, expression_count));
-                }
-                _ => {
-                    let symbol = self.get_symbol(&og_name);
-                    let symbol = match symbol {
-                        Some(symbol) => symbol.clone(),
-                        None => panic!(
-                            "Could not find the following in our symbol tables: '{}'",
-                            name
-                        ),
-                    };
-                    self.push_symbol(&symbol);
+                    "(" | "." => TermKind::Call(self.parse_subroutine_call(tokens_iter, name)?),
+                    _ => TermKind::Variable(name),
                 }
             }
-        }
-
-        self.output_padding -= 2;
-        self.save_to_output("</term>");
+            _ => {
+                return Err(self.error(
+                    next,
+                    &format!("expected an expression but found '{}'", next.source_text()),
+                ));
+            }
+        };
+        Ok(Term { kind, span })
     }
 
-    pub fn process_expression_list<'a, I: Iterator<Item = &'a Token>>(
+    /// The rest of a call once its first name has been read
+    fn parse_subroutine_call<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-    ) -> i32 {
-        self.save_to_output("<expressionList>");
-        self.output_padding += 2;
-
-        let mut peek = tokens_iter.peek().unwrap();
-        let mut counter = 0;
-        loop {
-            if peek.token_str == ")" {
-                // meaning expression list has ended
-                break;
+        first_name: Identifier,
+    ) -> Result<SubroutineCall, Diagnostic> {
+        let peek = self.peek_token(tokens_iter)?;
+        let (receiver, name) = match peek.token_str.as_str() {
+            "(" => (None, first_name),
+            "." => {
+                tokens_iter.next();
+                (Some(first_name), self.expect_identifier(tokens_iter)?)
             }
-
-            if counter > 0 {
-                self.process_specific(tokens_iter, String::from(","), TokenType::Symbol);
+            _ => {
+                return Err(self.error(
+                    peek,
+                    &format!("expected '(' or '.' but found '{}'", peek.source_text()),
+                ));
             }
-
-            self.process_expression(tokens_iter);
-
-            peek = tokens_iter.peek().unwrap();
-            counter += 1;
-        }
-
-        self.output_padding -= 2;
-        self.save_to_output("</expressionList>");
-        counter
+        };
+        self.expect(tokens_iter, "(", TokenType::Symbol)?;
+        let arguments = self.parse_expression_list(tokens_iter)?;
+        self.expect(tokens_iter, ")", TokenType::Symbol)?;
+
+        Ok(SubroutineCall {
+            receiver,
+            name,
+            arguments,
+        })
     }
 
-    pub fn process_subroutinecall<'a, I: Iterator<Item = &'a Token>>(
+    fn parse_expression_list<'a, I: Iterator<Item = &'a Token>>(
         &mut self,
         tokens_iter: &mut Peekable<I>,
-    ) {
-        // do
-        self.process_type(tokens_iter, TokenType::Keyword);
-        // class, var or subroutine Name
-        let mut name = self.process_type(tokens_iter, TokenType::Identifier);
-        let og_name = name.clone(); // for use later if name gets changed
-        let mut method_call = false;
-        let mut expression_count = 0;
-
-        // we want to change the name from the variable name
-        // to the type if this is the case
-        let symbol_check = self.get_symbol(&og_name);
-        if let Some(symbol) = symbol_check {
-            expression_count += 1;
-            name = symbol.var_type.clone();
-            method_call = true;
-        }
-
-        let peek = tokens_iter.peek().unwrap();
-        match peek.token_str.as_str() {
-            "(" => {
-                // if using a method of the current object, need to push in this
-                self.write_code("push pointer 0");
-
-                self.process_specific(tokens_iter, String::from("("), TokenType::Symbol);
-                name = format!("{}.{}", self.class_type, name);
-                expression_count = self.process_expression_list(tokens_iter);
-                expression_count += 1; // implies we are using a method of the current object
-                self.process_specific(tokens_iter, String::from(")"), TokenType::Symbol);
+    ) -> Result<Vec<Expression>, Diagnostic> {
+        let mut expressions = vec![];
+        loop {
+            let peek = self.peek_token(tokens_iter)?;
+            if peek.token_str == ")" {
+                // meaning expression list has ended
+                return Ok(expressions);
             }
-            "." => {
-                if method_call {
-                    // symbol table shouldn't have changed by this point
-                    let symbol = self.get_symbol(&og_name).unwrap().clone();
-                    self.push_symbol(&symbol);
-                }
 
-                name += &self.process_specific(tokens_iter, String::from("."), TokenType::Symbol);
-                name += &self.process_type(tokens_iter, TokenType::Identifier);
-                self.process_specific(tokens_iter, String::from("("), TokenType::Symbol);
-                expression_count += self.process_expression_list(tokens_iter);
-                self.process_specific(tokens_iter, String::from(")"), TokenType::Symbol);
+            if !expressions.is_empty() {
+                if peek.token_str != "," {
+                    return Err(self.error(
+                        peek,
+                        &format!("expected ',' or ')' but found '{}'", peek.source_text()),
+                    ));
+                }
+                tokens_iter.next();
             }
-            _ => {}
+            expressions.push(self.parse_expression(tokens_iter)?);
         }
-        self.process_specific(tokens_iter, String::from(";"), TokenType::Symbol);
-        self.write_code(&format!("call {} {}", name, expression_count));
-        self.write_code("pop temp 0");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::compiler::parser::Compiler;
+    use crate::compiler::type_check::Strictness;
+
+    #[test]
+    fn test_recover_at_statements() {
+        let source = [
+            "class Main {",
+            "    function void main() {",
+            "        var int x;",
+            "        let x = 5",
+            "        let y = 3;",
+            "        if (x) { let x = ; } else { let x = 1; }",
+            "        return;",
+            "    }",
+            "}",
+        ];
+        let mut compiler = Compiler::new(PathBuf::from("Main.jack"), false);
+        // only the syntax errors are of interest here
+        compiler.strictness = Strictness::Off;
+        compiler.lints = false;
+        compiler.compile_source(&source.join("\n"));
+
+        let errors: Vec<String> = compiler
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "5:9: expected ';' but found 'let'",
+                "5:13: 'y' isn't a declared variable",
+                "6:26: expected an expression but found ';'",
+            ]
+        );
     }
 }
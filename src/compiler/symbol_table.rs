@@ -1,5 +1,6 @@
 use std::{collections::BTreeMap, fmt::Display, str::FromStr};
 
+use crate::compiler::ast::{Class, Identifier, Subroutine, SubroutineKind};
 use crate::compiler::parser::Compiler;
 
 #[derive(Clone, Debug)]
@@ -137,4 +138,75 @@ impl Compiler {
         }
         subroutine_symbol
     }
+
+    /// Fills the class symbol table from the class's static and field declarations.
+    /// A name declared again keeps its first declaration, the repeats are given back
+    pub fn declare_class_variables<'a>(&mut self, class: &'a Class) -> Vec<&'a Identifier> {
+        self.class_type = class.name.name.clone();
+        self.class_symbol_table = SymbolTable::new();
+
+        let mut redeclared = vec![];
+        for variables in &class.variables {
+            let kind = variables.kind.to_string();
+            for name in &variables.names {
+                if self.class_symbol_table.get_symbol(&name.name).is_some() {
+                    redeclared.push(name);
+                    continue;
+                }
+                let index = self.class_symbol_table.get_index(&kind);
+                self.class_symbol_table.insert_symbol(
+                    name.name.clone(),
+                    variables.var_type.to_string(),
+                    kind.clone(),
+                    index,
+                );
+                self.class_symbol_table.increment_index(&kind);
+            }
+        }
+        redeclared
+    }
+
+    /// Starts a new subroutine symbol table with its arguments and local variables.
+    /// A method gets `this` as its first argument. Like for the class, a name declared
+    /// again keeps its first declaration and the repeats are given back
+    pub fn declare_subroutine_variables<'a>(
+        &mut self,
+        subroutine: &'a Subroutine,
+    ) -> Vec<&'a Identifier> {
+        self.subroutine_symbol_table = SymbolTable::new();
+        self.subroutine_kind = subroutine.kind;
+        if subroutine.kind == SubroutineKind::Method {
+            self.declare_local("this", self.class_type.clone(), "arg");
+        }
+        let mut redeclared = vec![];
+        for parameter in &subroutine.parameters {
+            if !self.declare_local(&parameter.name.name, parameter.var_type.to_string(), "arg") {
+                redeclared.push(&parameter.name);
+            }
+        }
+        for locals in &subroutine.locals {
+            for name in &locals.names {
+                if !self.declare_local(&name.name, locals.var_type.to_string(), "var") {
+                    redeclared.push(name);
+                }
+            }
+        }
+        redeclared
+    }
+
+    /// Whether the name was new to the subroutine
+    fn declare_local(&mut self, name: &str, var_type: String, kind: &str) -> bool {
+        if self.subroutine_symbol_table.get_symbol(name).is_some() {
+            return false;
+        }
+        let index = self.subroutine_symbol_table.get_index(kind);
+        self.subroutine_symbol_table.insert_symbol(
+            name.to_string(),
+            var_type,
+            kind.to_string(),
+            index,
+        );
+        self.subroutine_symbol_table.increment_index(kind);
+        true
+    }
 }
@@ -1,5 +1,8 @@
 use std::fmt::Display;
+use std::iter::Peekable;
+use std::str::Chars;
 
+use crate::compiler::diagnostics::{Diagnostic, Span};
 use crate::compiler::parser::Compiler;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -23,10 +26,24 @@ impl Display for TokenType {
     }
 }
 
+impl TokenType {
+    /// How the type reads in an error message
+    pub fn description(&self) -> &'static str {
+        match self {
+            TokenType::Keyword => "a keyword",
+            TokenType::Symbol => "a symbol",
+            TokenType::Identifier => "an identifier",
+            TokenType::StringConstant => "a string constant",
+            TokenType::IntegerConstant => "an integer constant",
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Token {
     pub token_str: String,
     pub token_type: TokenType,
+    pub span: Span,
 }
 
 impl Token {
@@ -34,6 +51,18 @@ impl Token {
         Token {
             token_str,
             token_type,
+            span: Span::default(),
+        }
+    }
+
+    /// The token as it was written in the source, without the xml escaping
+    pub fn source_text(&self) -> &str {
+        match self.token_str.as_str() {
+            "&lt;" => "<",
+            "&gt;" => ">",
+            "&quot;" => "\"",
+            "&amp;" => "&",
+            text => text,
         }
     }
 }
@@ -54,25 +83,50 @@ impl Display for Token {
     }
 }
 
-impl Compiler {
-    fn skip_line(&mut self, text_line: &str) -> bool {
-        if text_line.starts_with("//") | text_line.is_empty() {
-            return true;
-        }
-        if text_line.starts_with("/*") {
-            self.multi_line_comment = true;
-        }
-        if self.multi_line_comment & text_line.ends_with("*/") {
-            self.multi_line_comment = false;
-            return true;
-        }
-        if self.multi_line_comment {
-            return true;
+/// Walks through the source a character at a time, keeping track of where it is
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl Cursor<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    /// The character after the next one
+    fn peek_second(&self) -> Option<char> {
+        self.chars.clone().nth(1)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let next = self.chars.next()?;
+        if next == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
         }
+        Some(next)
+    }
 
-        false
+    /// Takes characters for as long as `keep` holds
+    fn take_while(&mut self, keep: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(next) = self.peek().filter(|next| keep(*next)) {
+            taken.push(next);
+            self.next();
+        }
+        taken
     }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
 
+impl Compiler {
     fn make_token(&mut self, token_str: String) -> Token {
         if self.symbols_list.contains(&token_str) {
             return Token::new(token_str, TokenType::Symbol);
@@ -83,85 +137,203 @@ impl Compiler {
         if token_str.chars().next().unwrap().is_numeric() {
             return Token::new(token_str, TokenType::IntegerConstant);
         }
-        if token_str.starts_with('"') {
-            return Token::new(
-                token_str.trim_matches('"').to_string(),
-                TokenType::StringConstant,
-            );
-        }
 
         Token::new(token_str, TokenType::Identifier)
     }
 
-    pub fn tokenize(&mut self, text_line: &str) {
-        let trimmed_line = text_line.trim();
-        if self.skip_line(trimmed_line) {
-            return;
-        }
+    fn push_token(&mut self, token: Token, span: Span) {
+        self.tokens.push(Token { span, ..token });
+    }
 
-        // remove inline comments
-        if self.debug {
-            println!("{}", trimmed_line);
-        }
-        let ready_line = if trimmed_line.contains("//") {
-            let (cleaned_line, _) = trimmed_line.split_once("//").unwrap();
-            cleaned_line
-        } else {
-            trimmed_line
+    fn lex_error(&mut self, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic::new(span, message));
+    }
+
+    /// Splits a whole .jack file into tokens. Anything that can't be a token is
+    /// reported in `diagnostics` and skipped
+    pub fn tokenize(&mut self, source: &str) {
+        let mut cursor = Cursor {
+            chars: source.chars().peekable(),
+            line: 1,
+            column: 1,
         };
 
-        let mut token = String::new();
-        let mut string_literal = false;
-        for char in ready_line.chars() {
-            // check for whitespaces
-            // push existing token if we have one
-            // assign expression booleans if relevant
-            if char.is_whitespace() & !token.is_empty() & !string_literal {
-                let token_type = self.make_token(token);
-                self.tokens.push(token_type);
-                token = String::new();
+        while let Some(next) = cursor.peek() {
+            let (line, column) = (cursor.line, cursor.column);
+            let span_to = |cursor: &Cursor| Span::new(line, column, cursor.column - column);
+
+            if next.is_whitespace() {
+                cursor.next();
+                continue;
             }
 
-            // We want to keep whitespaces when dealing
-            // with a string literal
-            if char.is_whitespace() {
-                // if we are in a string literal,
-                // we do want to push the whitespace
-                if string_literal {
-                    token.push(char);
+            // comments: `// ...` to the end of the line, `/* ... */` and `/** ... */` anywhere
+            if next == '/' && cursor.peek_second() == Some('/') {
+                cursor.take_while(|c| c != '\n');
+                continue;
+            }
+            if next == '/' && cursor.peek_second() == Some('*') {
+                cursor.next();
+                cursor.next();
+                let mut closed = false;
+                while let Some(c) = cursor.next() {
+                    if c == '*' && cursor.peek() == Some('/') {
+                        cursor.next();
+                        closed = true;
+                        break;
+                    }
+                }
+                if !closed {
+                    self.lex_error(
+                        Span::new(line, column, 2),
+                        String::from("unterminated comment, it needs a closing '*/'"),
+                    );
                 }
                 continue;
             }
 
-            // our string literal toggle
-            if char == '"' {
-                string_literal = !string_literal;
+            if next == '"' {
+                cursor.next();
+                let text = cursor.take_while(|c| c != '"' && c != '\n');
+                if cursor.peek() != Some('"') {
+                    let span = span_to(&cursor);
+                    self.lex_error(
+                        span,
+                        String::from(
+                            "unterminated string, it needs a closing '\"' on the same line",
+                        ),
+                    );
+                    continue;
+                }
+                cursor.next();
+                let span = span_to(&cursor);
+                if let Some(bad) = text
+                    .chars()
+                    .find(|c| !self.character_set.contains_key(&c.to_string()))
+                {
+                    self.lex_error(
+                        span,
+                        format!("'{}' isn't in the Jack character set", bad.escape_debug()),
+                    );
+                    continue;
+                }
+                self.push_token(Token::new(text, TokenType::StringConstant), span);
+                continue;
             }
 
-            // deal with general symbols
-            if self.symbols_list.contains(&char.to_string()) & !string_literal {
-                if !token.is_empty() {
-                    let token_type = self.make_token(token);
-                    self.tokens.push(token_type);
-                    token = String::new();
+            if next.is_ascii_digit() {
+                let word = cursor.take_while(is_word_char);
+                let span = span_to(&cursor);
+                if !word.chars().all(|c| c.is_ascii_digit()) {
+                    self.lex_error(
+                        span,
+                        format!(
+                            "'{}' isn't a valid integer constant or identifier, identifiers can't start with a digit",
+                            word
+                        ),
+                    );
+                    continue;
                 }
-                let char_string = char.to_string();
-                // some symbols need to be converted
-                // otherwise use symbol normally
-                let symbol_token = self.funky_symbols.get(&char_string).unwrap_or(&char_string);
+                let token = self.make_token(word);
+                self.push_token(token, span);
+                continue;
+            }
 
-                let token_type = self.make_token(symbol_token.clone());
-                self.tokens.push(token_type);
+            if next.is_ascii_alphabetic() || next == '_' {
+                let word = cursor.take_while(is_word_char);
+                let span = span_to(&cursor);
+                let token = self.make_token(word);
+                self.push_token(token, span);
                 continue;
             }
 
-            // now push to char
-            token.push(char);
-        }
-        // if we reached the end of the line, push in recent token
-        if !token.is_empty() {
-            let token_type = self.make_token(token);
-            self.tokens.push(token_type);
+            cursor.next();
+            let span = span_to(&cursor);
+            let char_string = next.to_string();
+            if !self.symbols_list.contains(&char_string) {
+                self.lex_error(
+                    span,
+                    format!("unexpected character '{}'", next.escape_debug()),
+                );
+                continue;
+            }
+            // some symbols need to be converted
+            // otherwise use symbol normally
+            let symbol_token = self
+                .funky_symbols
+                .get(&char_string)
+                .unwrap_or(&char_string)
+                .clone();
+            let token = self.make_token(symbol_token);
+            self.push_token(token, span);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn tokenize(source: &str) -> Compiler {
+        let mut compiler = Compiler::new(PathBuf::from("Main.jack"), false);
+        compiler.tokenize(source);
+        compiler
+    }
+
+    #[test]
+    fn test_tokenize_comments_and_strings() {
+        let compiler = tokenize(
+            "let /* mid */ x = \"a // b /* c\"; /** doc */ do f(); // end\n/* two\nlines */ return x<1;",
+        );
+        let tokens: Vec<String> = compiler
+            .tokens
+            .iter()
+            .map(|token| token.token_str.clone())
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                "let",
+                "x",
+                "=",
+                "a // b /* c",
+                ";",
+                "do",
+                "f",
+                "(",
+                ")",
+                ";",
+                "return",
+                "x",
+                "&lt;",
+                "1",
+                ";"
+            ]
+        );
+        assert!(compiler.diagnostics.is_empty());
+        // "return" starts the third line
+        assert_eq!(compiler.tokens[10].span, Span::new(3, 10, 6));
+        assert_eq!(compiler.tokens[3].span, Span::new(1, 19, 13));
+    }
+
+    #[test]
+    fn test_tokenize_errors() {
+        let compiler = tokenize("let x = \"open;\nlet 2x = 1 # 2;\n/* never closed");
+        let errors: Vec<String> = compiler
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "1:9: unterminated string, it needs a closing '\"' on the same line",
+                "2:5: '2x' isn't a valid integer constant or identifier, identifiers can't start with a digit",
+                "2:12: unexpected character '#'",
+                "3:1: unterminated comment, it needs a closing '*/'",
+            ]
+        );
+    }
+}
@@ -0,0 +1,554 @@
+use clap::ValueEnum;
+
+use crate::compiler::ast::{
+    Class, Expression, KeywordConstant, LetStatement, Op, Statement, Subroutine, SubroutineCall,
+    SubroutineKind, Term, TermKind, Type, UnaryOp,
+};
+use crate::compiler::diagnostics::{Diagnostic, Span};
+use crate::compiler::parser::Compiler;
+use crate::compiler::registry::{CallTarget, ClassRegistry};
+use crate::compiler::tokens::{Token, TokenType};
+
+/// How hard the type checker is on code Jack itself would accept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Strictness {
+    /// No type checking at all
+    Off,
+    /// Mixing char, int and Array, an Array as an object, or an int as a condition, is a
+    /// warning
+    Lenient,
+    /// Every mismatch is an error
+    Strict,
+}
+
+/// How a value's type fits the type it's used as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fit {
+    Same,
+    /// Jack treats them as the same 16 bit value, but it's usually a mistake
+    Interchangeable,
+    Mismatch,
+}
+
+/// char, int and Array are all just numbers to the vm
+fn is_number_like(var_type: &Type) -> bool {
+    match var_type {
+        Type::Int | Type::Char => true,
+        Type::Class(name) => name == "Array",
+        _ => false,
+    }
+}
+
+/// `None` stands for a type that isn't known, like `null` or an element of an Array,
+/// and fits anything
+fn fit(expected: &Type, found: &Option<Type>) -> Fit {
+    match found {
+        None => Fit::Same,
+        Some(found) if found == expected => Fit::Same,
+        // every char is a valid int
+        Some(Type::Char) if *expected == Type::Int => Fit::Same,
+        // an Array is how Jack points at any memory, so any object can be passed as one,
+        // like `Memory.deAlloc(this)`
+        Some(Type::Class(_)) if *expected == Type::Class(String::from("Array")) => Fit::Same,
+        // and the other way round is how Jack casts, like `let s = Memory.alloc(3)`
+        Some(Type::Class(name)) if name == "Array" && matches!(expected, Type::Class(_)) => {
+            Fit::Interchangeable
+        }
+        Some(found) if is_number_like(expected) && is_number_like(found) => Fit::Interchangeable,
+        Some(_) => Fit::Mismatch,
+    }
+}
+
+impl Compiler {
+    /// Checks every call against the subroutines in the registry, and assignments,
+    /// arguments, return values, array indexing and conditions against the declared types
+    pub fn check_class(&mut self, class: &Class, registry: &ClassRegistry) {
+        self.declare_class_variables(class);
+        for subroutine in &class.subroutines {
+            self.declare_subroutine_variables(subroutine);
+            self.check_statements(registry, subroutine, &subroutine.statements);
+        }
+    }
+
+    fn check_statements(
+        &mut self,
+        registry: &ClassRegistry,
+        subroutine: &Subroutine,
+        statements: &[Statement],
+    ) {
+        for statement in statements {
+            match statement {
+                Statement::Let(statement) => self.check_let(registry, statement),
+                Statement::If(statement) => {
+                    self.check_condition(registry, &statement.condition, "if");
+                    self.check_statements(registry, subroutine, &statement.then_statements);
+                    if let Some(else_statements) = &statement.else_statements {
+                        self.check_statements(registry, subroutine, else_statements);
+                    }
+                }
+                Statement::While(statement) => {
+                    self.check_condition(registry, &statement.condition, "while");
+                    self.check_statements(registry, subroutine, &statement.statements);
+                }
+                Statement::Do(statement) => {
+                    self.check_call(registry, &statement.call);
+                }
+                Statement::Return(statement) => {
+                    let name = &subroutine.name.name;
+                    let expected = &subroutine.return_type;
+                    match (&statement.value, expected) {
+                        (Some(value), Type::Void) => {
+                            self.check_expression(registry, value);
+                            self.type_error(
+                                value.term.span,
+                                format!("'{}' is void but returns a value", name),
+                            );
+                        }
+                        (Some(value), _) => {
+                            let found = self.check_expression(registry, value);
+                            self.check_fit(expected, &found, value.term.span, |found| {
+                                format!(
+                                    "'{}' should return {} but returns {}",
+                                    name,
+                                    expected.description(),
+                                    found
+                                )
+                            });
+                        }
+                        (None, Type::Void) => {}
+                        (None, _) => self.type_error(
+                            statement.span,
+                            format!(
+                                "'{}' should return {} but returns nothing",
+                                name,
+                                expected.description()
+                            ),
+                        ),
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_let(&mut self, registry: &ClassRegistry, statement: &LetStatement) {
+        let name = &statement.name;
+        let declared = self.variable_type(&name.name);
+        match &statement.index {
+            // array elements can hold anything
+            Some(index) => {
+                self.check_indexing(&name.name, &declared, name.span);
+                self.check_index(registry, index);
+                self.check_expression(registry, &statement.value);
+            }
+            None => {
+                let found = self.check_expression(registry, &statement.value);
+                if let Some(declared) = declared {
+                    self.check_fit(&declared, &found, statement.value.term.span, |found| {
+                        format!(
+                            "can't assign {} to '{}', it's {}",
+                            found,
+                            name.name,
+                            declared.description()
+                        )
+                    });
+                }
+            }
+        }
+    }
+
+    fn check_condition(
+        &mut self,
+        registry: &ClassRegistry,
+        condition: &Expression,
+        statement: &str,
+    ) {
+        let found = self.check_expression(registry, condition);
+        let context = format!("the {} condition should be a boolean", statement);
+        match found {
+            // testing bits with `&` gives an int, which Jack happily branches on
+            Some(Type::Int) => {
+                self.type_warning(condition.term.span, format!("{} but is an int", context))
+            }
+            _ => self.check_fit(&Type::Boolean, &found, condition.term.span, |found| {
+                format!("{} but is {}", context, found)
+            }),
+        }
+    }
+
+    fn check_index(&mut self, registry: &ClassRegistry, index: &Expression) {
+        let found = self.check_expression(registry, index);
+        self.check_fit(&Type::Int, &found, index.term.span, |found| {
+            format!("an array index should be an int but is {}", found)
+        });
+    }
+
+    /// Only an Array can be indexed, though Jack lets an int or char stand in for one
+    fn check_indexing(&mut self, name: &str, var_type: &Option<Type>, span: Span) {
+        let Some(var_type) = var_type else {
+            return;
+        };
+        match var_type {
+            Type::Class(class_name) if class_name == "Array" => {}
+            Type::Int | Type::Char => self.type_warning(
+                span,
+                format!("'{}' is indexed but is {}", name, var_type.description()),
+            ),
+            _ => self.type_error(
+                span,
+                format!("can't index '{}', it's {}", name, var_type.description()),
+            ),
+        }
+    }
+
+    /// The type of the expression, after checking every term and operator in it
+    fn check_expression(
+        &mut self,
+        registry: &ClassRegistry,
+        expression: &Expression,
+    ) -> Option<Type> {
+        let span = expression.term.span;
+        let mut left = self.check_term(registry, &expression.term);
+        for (op, term) in &expression.operations {
+            let right = self.check_term(registry, term);
+            left = match op {
+                Op::Eq => Some(Type::Boolean),
+                Op::And | Op::Or => {
+                    if left == Some(Type::Boolean) && right == Some(Type::Boolean) {
+                        Some(Type::Boolean)
+                    } else {
+                        // booleans are all ones or all zeros, so mixing them in with ints is fine
+                        if left != Some(Type::Boolean) {
+                            self.check_operand(*op, &left, span);
+                        }
+                        if right != Some(Type::Boolean) {
+                            self.check_operand(*op, &right, term.span);
+                        }
+                        Some(Type::Int)
+                    }
+                }
+                Op::Lt | Op::Gt => {
+                    self.check_operand(*op, &left, span);
+                    self.check_operand(*op, &right, term.span);
+                    Some(Type::Boolean)
+                }
+                Op::Add | Op::Sub | Op::Mul | Op::Div => {
+                    self.check_operand(*op, &left, span);
+                    self.check_operand(*op, &right, term.span);
+                    Some(Type::Int)
+                }
+            };
+        }
+        left
+    }
+
+    fn check_operand(&mut self, op: Op, found: &Option<Type>, span: Span) {
+        let token = Token::new(op.symbol().to_string(), TokenType::Symbol);
+        let symbol = token.source_text();
+        self.check_fit(&Type::Int, found, span, |found| {
+            format!(
+                "'{}' should be used on an int but is used on {}",
+                symbol, found
+            )
+        });
+    }
+
+    fn check_term(&mut self, registry: &ClassRegistry, term: &Term) -> Option<Type> {
+        match &term.kind {
+            TermKind::IntegerConstant(_) => Some(Type::Int),
+            TermKind::StringConstant(_) => Some(Type::Class(String::from("String"))),
+            TermKind::Keyword(KeywordConstant::True | KeywordConstant::False) => {
+                Some(Type::Boolean)
+            }
+            TermKind::Keyword(KeywordConstant::Null) => None,
+            TermKind::Keyword(KeywordConstant::This) => Some(Type::Class(self.class_type.clone())),
+            TermKind::Variable(name) => self.variable_type(&name.name),
+            TermKind::ArrayAccess(name, index) => {
+                let var_type = self.variable_type(&name.name);
+                self.check_indexing(&name.name, &var_type, name.span);
+                self.check_index(registry, index);
+                None
+            }
+            TermKind::Call(call) => match self.check_call(registry, call) {
+                Some(Type::Void) => {
+                    let (class_name, _) = self.resolve_call(call);
+                    self.call_error(
+                        term.span,
+                        format!(
+                            "'{}.{}' is void, so it has no value to use",
+                            class_name, call.name.name
+                        ),
+                    );
+                    None
+                }
+                found => found,
+            },
+            TermKind::Parenthesized(expression) => self.check_expression(registry, expression),
+            TermKind::Unary(UnaryOp::Neg, operand) => {
+                let found = self.check_term(registry, operand);
+                self.check_fit(&Type::Int, &found, operand.span, |found| {
+                    format!("'-' should be used on an int but is used on {}", found)
+                });
+                Some(Type::Int)
+            }
+            TermKind::Unary(UnaryOp::Not, operand) => {
+                let found = self.check_term(registry, operand);
+                if found == Some(Type::Boolean) {
+                    return found;
+                }
+                self.check_fit(&Type::Int, &found, operand.span, |found| {
+                    format!(
+                        "'~' should be used on a boolean or an int but is used on {}",
+                        found
+                    )
+                });
+                Some(Type::Int)
+            }
+        }
+    }
+
+    /// Checks the call goes to a subroutine that exists, in the way it's declared and
+    /// with the right arguments, and gives back what it returns, which can be void
+    fn check_call(&mut self, registry: &ClassRegistry, call: &SubroutineCall) -> Option<Type> {
+        let argument_types: Vec<(Option<Type>, Span)> = call
+            .arguments
+            .iter()
+            .map(|argument| {
+                (
+                    self.check_expression(registry, argument),
+                    argument.term.span,
+                )
+            })
+            .collect();
+
+        let (class_name, target) = self.resolve_call(call);
+        let name = &call.name;
+        let full_name = format!("{}.{}", class_name, name.name);
+        let receiver_span = call
+            .receiver
+            .as_ref()
+            .map_or(name.span, |receiver| receiver.span);
+
+        if let CallTarget::Variable(symbol) = &target {
+            let var_type = Type::from_name(&symbol.var_type);
+            if !matches!(var_type, Type::Class(_)) {
+                self.call_error(
+                    receiver_span,
+                    format!(
+                        "can't call a method on '{}', it's {}",
+                        call.receiver.as_ref().map_or("", |receiver| &receiver.name),
+                        var_type.description()
+                    ),
+                );
+                return None;
+            }
+        }
+        // the class's own parse errors are already reported
+        if registry.is_unparsed(&class_name) {
+            return None;
+        }
+        if !registry.has_class(&class_name) {
+            let message = match &target {
+                CallTarget::Variable(_) => format!(
+                    "'{}' is {}, but there's no class '{}'",
+                    call.receiver.as_ref().map_or("", |receiver| &receiver.name),
+                    Type::Class(class_name.clone()).description(),
+                    class_name
+                ),
+                _ => format!("'{}' isn't a class or a declared variable", class_name),
+            };
+            self.call_error(receiver_span, message);
+            return None;
+        }
+        let Some(signature) = registry.signature(&class_name, &name.name) else {
+            self.call_error(
+                name.span,
+                format!("'{}' has no subroutine '{}'", class_name, name.name),
+            );
+            return None;
+        };
+
+        match (&target, signature.kind) {
+            // a function has no current object to call the method on
+            (CallTarget::This, SubroutineKind::Method)
+                if self.subroutine_kind == SubroutineKind::Function =>
+            {
+                self.call_error(
+                    name.span,
+                    format!(
+                        "'{}' is a method, so a function has to call it on a {} object",
+                        full_name, class_name
+                    ),
+                )
+            }
+            (CallTarget::This, SubroutineKind::Method) => {}
+            (CallTarget::This, kind) => self.call_error(
+                name.span,
+                format!(
+                    "'{}' is a {}, so it's called as {}()",
+                    full_name, kind, full_name
+                ),
+            ),
+            (CallTarget::Variable(_), SubroutineKind::Method) => {}
+            (CallTarget::Variable(_), kind) => self.call_error(
+                name.span,
+                format!(
+                    "'{}' is a {}, so it's called as {}() rather than on a variable",
+                    full_name, kind, full_name
+                ),
+            ),
+            (CallTarget::Class, SubroutineKind::Method) => self.call_error(
+                name.span,
+                format!(
+                    "'{}' is a method, so it has to be called on a {} object",
+                    full_name, class_name
+                ),
+            ),
+            (CallTarget::Class, _) => {}
+        }
+
+        if argument_types.len() != signature.parameters.len() {
+            self.call_error(
+                name.span,
+                format!(
+                    "'{}' takes {} argument{} but is given {}",
+                    full_name,
+                    signature.parameters.len(),
+                    if signature.parameters.len() == 1 {
+                        ""
+                    } else {
+                        "s"
+                    },
+                    argument_types.len()
+                ),
+            );
+        }
+        for (number, (parameter, (found, span))) in
+            signature.parameters.iter().zip(argument_types).enumerate()
+        {
+            self.check_fit(parameter, &found, span, |found| {
+                format!(
+                    "argument {} of '{}' should be {} but is {}",
+                    number + 1,
+                    full_name,
+                    parameter.description(),
+                    found
+                )
+            });
+        }
+        Some(signature.return_type.clone())
+    }
+
+    fn variable_type(&mut self, name: &str) -> Option<Type> {
+        self.get_symbol(name)
+            .map(|symbol| Type::from_name(&symbol.var_type))
+    }
+
+    /// Notes a problem if the found type doesn't fit, the message is given a description
+    /// of what was found
+    fn check_fit(
+        &mut self,
+        expected: &Type,
+        found: &Option<Type>,
+        span: Span,
+        message: impl FnOnce(&str) -> String,
+    ) {
+        let Some(found_type) = found else {
+            return;
+        };
+        let message = message(&found_type.description());
+        match fit(expected, found) {
+            Fit::Same => {}
+            Fit::Interchangeable => self.type_warning(span, message),
+            Fit::Mismatch => self.type_error(span, message),
+        }
+    }
+
+    fn call_error(&mut self, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic::new(span, message));
+    }
+
+    fn type_error(&mut self, span: Span, message: String) {
+        if self.strictness != Strictness::Off {
+            self.diagnostics.push(Diagnostic::new(span, message));
+        }
+    }
+
+    /// Something Jack allows but that's likely a mistake, only an error when strict
+    fn type_warning(&mut self, span: Span, message: String) {
+        let diagnostic = match self.strictness {
+            Strictness::Off => return,
+            Strictness::Strict => Diagnostic::new(span, message),
+            Strictness::Lenient => Diagnostic::warning(span, message),
+        };
+        self.diagnostics.push(diagnostic);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::Strictness;
+    use crate::compiler::parser::Compiler;
+
+    const SOURCE: &str = "class Main {
+    field int count;
+    function int twice(int x) { return x + x; }
+    function void main() {
+        var int x;
+        var char c;
+        var boolean b;
+        var Array a;
+        var String s;
+        let x = \"hello\";
+        let c = 65;
+        let a[b] = x;
+        let b = Main.twice(true);
+        do x.print();
+        let s = a;
+        if (x & 1) { return 1; }
+        return;
+    }
+}";
+
+    fn compile(strictness: Strictness) -> Vec<String> {
+        let mut compiler = Compiler::new(PathBuf::from("Main.jack"), false);
+        compiler.strictness = strictness;
+        compiler.lints = false;
+        compiler.compile_source(SOURCE);
+        compiler
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_check_types() {
+        assert_eq!(
+            compile(Strictness::Lenient),
+            [
+                "10:17: can't assign a String to 'x', it's an int",
+                "11:17: warning: can't assign an int to 'c', it's a char",
+                "12:15: an array index should be an int but is a boolean",
+                "13:17: can't assign an int to 'b', it's a boolean",
+                "13:28: argument 1 of 'Main.twice' should be an int but is a boolean",
+                "14:12: can't call a method on 'x', it's an int",
+                "15:17: warning: can't assign an Array to 's', it's a String",
+                "16:13: warning: the if condition should be a boolean but is an int",
+                "16:29: 'main' is void but returns a value",
+            ]
+        );
+        // calls are still checked without types
+        assert_eq!(
+            compile(Strictness::Off),
+            ["14:12: can't call a method on 'x', it's an int"]
+        );
+        let strict = compile(Strictness::Strict);
+        assert_eq!(strict[1], "11:17: can't assign an int to 'c', it's a char");
+        assert_eq!(
+            strict[6],
+            "15:17: can't assign an Array to 's', it's a String"
+        );
+    }
+}
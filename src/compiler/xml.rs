@@ -0,0 +1,262 @@
+use crate::compiler::ast::{
+    Class, ClassVarDec, Expression, Identifier, Statement, Subroutine, SubroutineCall, Term,
+    TermKind, Type,
+};
+use crate::compiler::tokens::{Token, TokenType};
+
+/// Writes the syntax tree in the course's xml format, every token on its own line
+/// and every node indented two spaces inside its parent
+#[derive(Debug, Default)]
+pub struct XmlWriter {
+    pub output: String,
+    padding: usize,
+}
+
+impl XmlWriter {
+    pub fn save_to_output(&mut self, grammar_string: &str) {
+        let spaces = " ".repeat(self.padding);
+        self.output += &format!("{}{}\n", spaces, grammar_string);
+    }
+
+    fn open(&mut self, tag: &str) {
+        self.save_to_output(&format!("<{}>", tag));
+        self.padding += 2;
+    }
+
+    fn close(&mut self, tag: &str) {
+        self.padding -= 2;
+        self.save_to_output(&format!("</{}>", tag));
+    }
+
+    fn token(&mut self, token_str: &str, token_type: TokenType) {
+        let token = Token::new(token_str.to_string(), token_type);
+        self.save_to_output(&token.to_string());
+    }
+
+    fn keyword(&mut self, keyword: &str) {
+        self.token(keyword, TokenType::Keyword);
+    }
+
+    fn symbol(&mut self, symbol: &str) {
+        self.token(symbol, TokenType::Symbol);
+    }
+
+    fn identifier(&mut self, identifier: &Identifier) {
+        self.token(&identifier.name, TokenType::Identifier);
+    }
+
+    fn var_type(&mut self, var_type: &Type) {
+        match var_type {
+            Type::Class(name) => self.token(name, TokenType::Identifier),
+            _ => self.keyword(&var_type.to_string()),
+        }
+    }
+
+    /// Names separated by commas and ending with a `;`
+    fn names(&mut self, names: &[Identifier]) {
+        for (i, name) in names.iter().enumerate() {
+            if i > 0 {
+                self.symbol(",");
+            }
+            self.identifier(name);
+        }
+        self.symbol(";");
+    }
+
+    pub fn class(&mut self, class: &Class) {
+        self.open("class");
+        self.keyword("class");
+        self.identifier(&class.name);
+        self.symbol("{");
+        for variables in &class.variables {
+            self.class_var_dec(variables);
+        }
+        for subroutine in &class.subroutines {
+            self.subroutine(subroutine);
+        }
+        self.symbol("}");
+        self.close("class");
+    }
+
+    fn class_var_dec(&mut self, variables: &ClassVarDec) {
+        self.open("classVarDec");
+        self.keyword(&variables.kind.to_string());
+        self.var_type(&variables.var_type);
+        self.names(&variables.names);
+        self.close("classVarDec");
+    }
+
+    fn subroutine(&mut self, subroutine: &Subroutine) {
+        self.open("subroutineDec");
+        self.keyword(&subroutine.kind.to_string());
+        self.var_type(&subroutine.return_type);
+        self.identifier(&subroutine.name);
+
+        self.symbol("(");
+        self.open("parameterList");
+        for (i, parameter) in subroutine.parameters.iter().enumerate() {
+            if i > 0 {
+                self.symbol(",");
+            }
+            self.var_type(&parameter.var_type);
+            self.identifier(&parameter.name);
+        }
+        self.close("parameterList");
+        self.symbol(")");
+
+        self.open("subroutineBody");
+        self.symbol("{");
+        for locals in &subroutine.locals {
+            self.open("varDec");
+            self.keyword("var");
+            self.var_type(&locals.var_type);
+            self.names(&locals.names);
+            self.close("varDec");
+        }
+        self.statements(&subroutine.statements);
+        self.symbol("}");
+        self.close("subroutineBody");
+        self.close("subroutineDec");
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        self.open("statements");
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.close("statements");
+    }
+
+    /// `{ statements }`
+    fn block(&mut self, statements: &[Statement]) {
+        self.symbol("{");
+        self.statements(statements);
+        self.symbol("}");
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let(statement) => {
+                self.open("letStatement");
+                self.keyword("let");
+                self.identifier(&statement.name);
+                if let Some(index) = &statement.index {
+                    self.symbol("[");
+                    self.expression(index);
+                    self.symbol("]");
+                }
+                self.symbol("=");
+                self.expression(&statement.value);
+                self.symbol(";");
+                self.close("letStatement");
+            }
+            Statement::If(statement) => {
+                self.open("ifStatement");
+                self.keyword("if");
+                self.symbol("(");
+                self.expression(&statement.condition);
+                self.symbol(")");
+                self.block(&statement.then_statements);
+                if let Some(else_statements) = &statement.else_statements {
+                    self.keyword("else");
+                    self.block(else_statements);
+                }
+                self.close("ifStatement");
+            }
+            Statement::While(statement) => {
+                self.open("whileStatement");
+                self.keyword("while");
+                self.symbol("(");
+                self.expression(&statement.condition);
+                self.symbol(")");
+                self.block(&statement.statements);
+                self.close("whileStatement");
+            }
+            Statement::Do(statement) => {
+                self.open("doStatement");
+                self.keyword("do");
+                self.subroutine_call(&statement.call);
+                self.symbol(";");
+                self.close("doStatement");
+            }
+            Statement::Return(statement) => {
+                self.open("returnStatement");
+                self.keyword("return");
+                if let Some(value) = &statement.value {
+                    self.expression(value);
+                }
+                self.symbol(";");
+                self.close("returnStatement");
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        self.open("expression");
+        self.term(&expression.term);
+        for (op, term) in &expression.operations {
+            self.symbol(op.symbol());
+            self.term(term);
+        }
+        self.close("expression");
+    }
+
+    fn term(&mut self, term: &Term) {
+        self.open("term");
+        match &term.kind {
+            TermKind::IntegerConstant(constant) => self.token(constant, TokenType::IntegerConstant),
+            TermKind::StringConstant(string) => self.token(string, TokenType::StringConstant),
+            TermKind::Keyword(keyword) => self.keyword(&keyword.to_string()),
+            TermKind::Variable(name) => self.identifier(name),
+            TermKind::ArrayAccess(name, index) => {
+                self.identifier(name);
+                self.symbol("[");
+                self.expression(index);
+                self.symbol("]");
+            }
+            TermKind::Call(call) => self.subroutine_call(call),
+            TermKind::Parenthesized(expression) => {
+                self.symbol("(");
+                self.expression(expression);
+                self.symbol(")");
+            }
+            TermKind::Unary(op, term) => {
+                self.symbol(op.symbol());
+                self.term(term);
+            }
+        }
+        self.close("term");
+    }
+
+    fn subroutine_call(&mut self, call: &SubroutineCall) {
+        if let Some(receiver) = &call.receiver {
+            self.identifier(receiver);
+            self.symbol(".");
+        }
+        self.identifier(&call.name);
+        self.symbol("(");
+        self.open("expressionList");
+        for (i, argument) in call.arguments.iter().enumerate() {
+            if i > 0 {
+                self.symbol(",");
+            }
+            self.expression(argument);
+        }
+        self.close("expressionList");
+        self.symbol(")");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn save_to_output() {
+        let grammar_string = "<class>";
+        let mut tabs = String::new();
+        let tabs_count = 4;
+        for _ in 0..tabs_count {
+            tabs += " ";
+        }
+        assert_eq!(format!("{}{}\n", tabs, grammar_string), "    <class>\n");
+    }
+}
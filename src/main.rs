@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use clap::{Parser, Subcommand};
 
 mod assembler;
@@ -5,21 +7,148 @@ mod compiler;
 mod vm;
 
 use assembler::run::run_assembler;
-use compiler::run::run_compiler;
-use vm::run::run_vm;
+use compiler::parser::Emit;
+use compiler::run::{CompileOptions, run_compiler};
+use compiler::type_check::Strictness;
+use vm::bootstrap::Bootstrap;
+use vm::c_backend::run_vm2c;
+use vm::callgraph::run_callgraph;
+use vm::compact::CodeGen;
+use vm::emulator::run_emulator;
+use vm::run::{VmOptions, run_vm};
+use vm::top_of_stack::Strategy;
 
 fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        Some(Commands::Assembler { file, debug }) => {
-            run_assembler(file, debug);
+        Some(Commands::Assembler {
+            file,
+            debug,
+            source_map,
+        }) => {
+            run_assembler(file, debug, source_map);
+        }
+        Some(Commands::Vm {
+            file,
+            debug,
+            no_bootstrap,
+            entry,
+            sp,
+            lcl,
+            arg,
+            this,
+            that,
+            end_loop,
+            code_gen,
+            strategy,
+            compare_strategies,
+            optimize,
+            tail_calls,
+            inline,
+            inline_threshold,
+            manifest,
+            annotate,
+            source_map,
+            statics,
+            verify,
+        }) => {
+            let bootstrap = Bootstrap {
+                call_entry: !no_bootstrap,
+                entry,
+                sp,
+                lcl,
+                arg,
+                this,
+                that,
+                end_loop,
+            };
+            let options = VmOptions {
+                bootstrap,
+                code_gen,
+                strategy,
+                compare_strategies,
+                optimize,
+                tail_calls,
+                inline: inline.then_some(inline_threshold),
+                manifest,
+                annotate,
+                source_map,
+                statics,
+                verify,
+            };
+            run_vm(file, options, debug);
+        }
+        Some(Commands::Callgraph { file, entry }) => {
+            run_callgraph(file, &entry);
         }
-        Some(Commands::Vm { file, debug }) => {
-            run_vm(file, debug);
+        Some(Commands::Vm2c {
+            file,
+            entry,
+            no_bootstrap,
+            sp,
+            lcl,
+            arg,
+            this,
+            that,
+        }) => {
+            let bootstrap = Bootstrap {
+                call_entry: !no_bootstrap,
+                entry,
+                sp,
+                lcl,
+                arg,
+                this,
+                that,
+                ..Default::default()
+            };
+            run_vm2c(file, bootstrap);
         }
-        Some(Commands::Compile { file, debug }) => {
-            run_compiler(file, debug);
+        Some(Commands::Emulate {
+            file,
+            entry,
+            no_bootstrap,
+            sp,
+            lcl,
+            arg,
+            this,
+            that,
+            steps,
+            ram,
+            profile,
+            folded,
+        }) => {
+            let bootstrap = Bootstrap {
+                call_entry: !no_bootstrap,
+                entry,
+                sp,
+                lcl,
+                arg,
+                this,
+                that,
+                ..Default::default()
+            };
+            run_emulator(file, bootstrap, steps, &ram, profile, folded);
+        }
+        Some(Commands::Compile {
+            file,
+            debug,
+            native_math,
+            type_check,
+            no_lints,
+            os_source,
+            emit,
+            out_dir,
+        }) => {
+            let options = CompileOptions {
+                native_math,
+                strictness: type_check,
+                lints: !no_lints,
+                os_source,
+                emit,
+                out_dir,
+            };
+            run_compiler(file, options, debug);
         }
         None => {}
     }
@@ -41,6 +170,10 @@ enum Commands {
 
         #[arg(long)]
         debug: bool,
+
+        /// Carry the .asm.json source map from the vm translator through to a .hack.json
+        #[arg(long)]
+        source_map: bool,
     },
     /// Takes in a .vm file or folder of .vm files and returns a single .asm file
     Vm {
@@ -49,6 +182,177 @@ enum Commands {
 
         #[arg(long)]
         debug: bool,
+
+        /// Don't set up the stack or call the entry function (e.g. project 7 tests)
+        #[arg(long)]
+        no_bootstrap: bool,
+
+        /// Function the bootstrap code calls into
+        #[arg(long, default_value = "Sys.init")]
+        entry: String,
+
+        /// Initial value of SP (defaults to 256 when bootstrapping)
+        #[arg(long)]
+        sp: Option<u16>,
+
+        /// Initial value of LCL
+        #[arg(long)]
+        lcl: Option<u16>,
+
+        /// Initial value of ARG
+        #[arg(long)]
+        arg: Option<u16>,
+
+        /// Initial value of THIS
+        #[arg(long)]
+        this: Option<u16>,
+
+        /// Initial value of THAT
+        #[arg(long)]
+        that: Option<u16>,
+
+        /// Finish with an infinite (END) loop once the program is done
+        #[arg(long)]
+        end_loop: bool,
+
+        /// Write calls, returns and comparisons inline or as jumps to shared routines
+        #[arg(long, value_enum, default_value_t = CodeGen::Inline)]
+        code_gen: CodeGen,
+
+        /// Keep every stack value in RAM or cache the top of the stack in D
+        #[arg(long, value_enum, default_value_t = Strategy::Stack)]
+        strategy: Strategy,
+
+        /// Print the output size of each stack strategy
+        #[arg(long)]
+        compare_strategies: bool,
+
+        /// Fold constants, drop dead code and unreachable functions before translating
+        #[arg(long)]
+        optimize: bool,
+
+        /// Compile `call f n` followed by `return` as a jump that reuses the current frame
+        #[arg(long)]
+        tail_calls: bool,
+
+        /// Copy small functions that make no calls into their callers
+        #[arg(long)]
+        inline: bool,
+
+        /// Largest function, in vm commands, that --inline will copy
+        #[arg(long, default_value_t = 10)]
+        inline_threshold: usize,
+
+        /// Text file listing the .vm files to translate, one per line in the order to use
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+
+        /// Write each vm command as a comment above the assembly it turned into
+        #[arg(long)]
+        annotate: bool,
+
+        /// Also write a .asm.json file mapping assembly lines back to vm file lines
+        #[arg(long)]
+        source_map: bool,
+
+        /// Print how many static variables each file uses out of RAM 16-255
+        #[arg(long)]
+        statics: bool,
+
+        /// Check that every function keeps the stack balanced before translating
+        #[arg(long)]
+        verify: bool,
+    },
+    /// Writes the call graph of a .vm file or folder of .vm files as .dot and .callgraph.json
+    Callgraph {
+        #[arg(default_value = ".")]
+        file: String,
+
+        /// Function the program starts from, used to find unreachable functions
+        #[arg(long, default_value = "Sys.init")]
+        entry: String,
+    },
+    /// Runs a .vm file or folder of .vm files without translating them
+    Emulate {
+        #[arg(default_value = ".")]
+        file: String,
+
+        /// Function the program starts from
+        #[arg(long, default_value = "Sys.init")]
+        entry: String,
+
+        /// Start at the first command instead of calling the entry function
+        #[arg(long)]
+        no_bootstrap: bool,
+
+        /// Initial value of SP (defaults to 256)
+        #[arg(long)]
+        sp: Option<u16>,
+
+        /// Initial value of LCL
+        #[arg(long)]
+        lcl: Option<u16>,
+
+        /// Initial value of ARG
+        #[arg(long)]
+        arg: Option<u16>,
+
+        /// Initial value of THIS
+        #[arg(long)]
+        this: Option<u16>,
+
+        /// Initial value of THAT
+        #[arg(long)]
+        that: Option<u16>,
+
+        /// Stop after running this many vm commands
+        #[arg(long, default_value_t = 10_000_000)]
+        steps: u64,
+
+        /// RAM addresses or ranges (e.g. 256-260) to print once the program stops
+        #[arg(long)]
+        ram: Vec<String>,
+
+        /// Print calls, commands run and stack use for each function
+        #[arg(long)]
+        profile: bool,
+
+        /// Write the commands run under each chain of calls to a folded stack file for flamegraphs
+        #[arg(long)]
+        folded: Option<PathBuf>,
+    },
+    /// Turns a .vm file or folder of .vm files into a single C file that runs them natively
+    Vm2c {
+        #[arg(default_value = ".")]
+        file: String,
+
+        /// Function the program starts from
+        #[arg(long, default_value = "Sys.init")]
+        entry: String,
+
+        /// Start at the first command instead of calling the entry function
+        #[arg(long)]
+        no_bootstrap: bool,
+
+        /// Initial value of SP (defaults to 256)
+        #[arg(long)]
+        sp: Option<u16>,
+
+        /// Initial value of LCL
+        #[arg(long)]
+        lcl: Option<u16>,
+
+        /// Initial value of ARG
+        #[arg(long)]
+        arg: Option<u16>,
+
+        /// Initial value of THIS
+        #[arg(long)]
+        this: Option<u16>,
+
+        /// Initial value of THAT
+        #[arg(long)]
+        that: Option<u16>,
     },
     /// Takes in a .jack file or folder of .jack files and returns corresponding .vm files
     Compile {
@@ -57,5 +361,29 @@ enum Commands {
 
         #[arg(long)]
         debug: bool,
+
+        /// Compile * and / to the extended mul and div vm commands instead of Math calls
+        #[arg(long)]
+        native_math: bool,
+
+        /// How strictly to check types, mixing char, int and Array is only an error when strict
+        #[arg(long, value_enum, default_value_t = Strictness::Lenient)]
+        type_check: Strictness,
+
+        /// Don't warn about unused, unassigned and shadowed variables
+        #[arg(long)]
+        no_lints: bool,
+
+        /// Folder of your own OS .jack files, whose subroutines are checked against instead of the bundled OS
+        #[arg(long)]
+        os_source: Option<PathBuf>,
+
+        /// Which files to write for each class, separated by commas
+        #[arg(long, value_enum, value_delimiter = ',', default_value = "vm")]
+        emit: Vec<Emit>,
+
+        /// Write the outputs to this folder instead of next to the .jack files
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
     },
 }
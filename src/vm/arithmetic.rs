@@ -1,8 +1,19 @@
+use crate::vm::compact::CodeGen;
+use crate::vm::math::is_math;
 use crate::vm::parser::Parser;
 
 impl Parser {
-    pub fn handle_arithmetic(&mut self, tokens: Vec<&str>) {
-        let arithmetic = tokens[0];
+    pub fn handle_arithmetic(&mut self, arithmetic: &str) {
+        if self.code_gen == CodeGen::Compact && ["eq", "gt", "lt"].contains(&arithmetic) {
+            // the shared routine moves the stack up itself
+            self.compact_comparison(arithmetic);
+            return;
+        }
+        if is_math(arithmetic) {
+            self.handle_math(arithmetic);
+            return;
+        }
+
         match arithmetic {
             "add" => self.add(),
             "sub" => self.sub(),
@@ -30,7 +41,7 @@ impl Parser {
         self.output += "D=M\n";
     }
 
-    fn get_values(&mut self) {
+    pub fn get_values(&mut self) {
         self.get_y();
 
         // now go down again and prep for x
@@ -0,0 +1,90 @@
+use crate::vm::parser::Parser;
+
+/// Controls the code emitted ahead of the translated .vm files
+#[derive(Debug, Clone)]
+pub struct Bootstrap {
+    /// Whether to call the entry function once the segments are set up
+    pub call_entry: bool,
+    pub entry: String,
+    pub sp: Option<u16>,
+    pub lcl: Option<u16>,
+    pub arg: Option<u16>,
+    pub this: Option<u16>,
+    pub that: Option<u16>,
+    /// Whether to trap execution in an (END) loop once the program is done
+    pub end_loop: bool,
+}
+
+impl Default for Bootstrap {
+    fn default() -> Self {
+        Bootstrap {
+            call_entry: true,
+            entry: String::from("Sys.init"),
+            sp: None,
+            lcl: None,
+            arg: None,
+            this: None,
+            that: None,
+            end_loop: false,
+        }
+    }
+}
+
+impl Parser {
+    pub fn bootstrap(&mut self, bootstrap: &Bootstrap) {
+        // the stack only needs a default when we are going to call into it
+        let sp = match bootstrap.sp {
+            Some(sp) => Some(sp),
+            None if bootstrap.call_entry => Some(256),
+            None => None,
+        };
+
+        for (pointer, value) in [
+            ("SP", sp),
+            ("LCL", bootstrap.lcl),
+            ("ARG", bootstrap.arg),
+            ("THIS", bootstrap.this),
+            ("THAT", bootstrap.that),
+        ] {
+            if let Some(value) = value {
+                self.output += &format!("@{}\n", value);
+                self.output += "D=A\n";
+                self.output += &format!("@{}\n", pointer);
+                self.output += "M=D\n";
+            }
+        }
+
+        if bootstrap.call_entry {
+            self.handle_call(&bootstrap.entry, 0);
+
+            if bootstrap.end_loop {
+                self.end_loop();
+            }
+        }
+    }
+
+    /// Spin forever so the CPU doesn't run off into whatever follows
+    pub fn end_loop(&mut self) {
+        self.output += "(END)\n";
+        self.output += "@END\n";
+        self.output += "0;JMP\n";
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bootstrap_without_entry_call() {
+        let mut parser = Parser::new(5);
+        let bootstrap = Bootstrap {
+            call_entry: false,
+            lcl: Some(300),
+            ..Default::default()
+        };
+        parser.bootstrap(&bootstrap);
+
+        assert_eq!(parser.output, "@300\nD=A\n@LCL\nM=D\n");
+    }
+}
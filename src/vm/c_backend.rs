@@ -0,0 +1,537 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::vm::bootstrap::Bootstrap;
+use crate::vm::commands::Command;
+use crate::vm::emulator::Emulator;
+use crate::vm::parser::{VmFile, parse_file};
+use crate::vm::run::{create_output_path, valid_files};
+
+/// RAM, the stack and call helpers and the keyboard, shared by every generated program.
+/// It behaves like the emulator, down to the error messages
+const RUNTIME: &str = r#"#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define RAM_SIZE 32768
+#define SCREEN 16384
+#define KBD 24576
+/* how many reads of KBD each key is held down for, and then let go for */
+#define KEY_READS 1000
+
+static int16_t ram[RAM_SIZE];
+static uint64_t steps;
+static uint64_t max_steps = UINT64_MAX;
+static uint32_t current;
+static const char *keys = "";
+static uint64_t key_reads;
+
+extern const char *const sources[];
+
+static void fail(const char *format, ...) {
+    va_list args;
+    va_start(args, format);
+    fprintf(stderr, "Error: %s: ", sources[current]);
+    vfprintf(stderr, format, args);
+    fprintf(stderr, "\n");
+    va_end(args);
+    exit(1);
+}
+
+static int16_t keyboard(void) {
+    uint64_t key = key_reads / (2 * KEY_READS);
+    int held = key_reads % (2 * KEY_READS) < KEY_READS;
+    if (key >= strlen(keys)) {
+        return 0;
+    }
+    key_reads++;
+    if (!held) {
+        return 0;
+    }
+    /* Jack's newline key */
+    return keys[key] == '\n' ? 128 : (unsigned char)keys[key];
+}
+
+static int16_t rd(int16_t address) {
+    if (address < 0) {
+        fail("read from outside of RAM at %d", address);
+    }
+    if (address == KBD) {
+        return keyboard();
+    }
+    return ram[address];
+}
+
+static void wr(int16_t address, int16_t value) {
+    if (address < 0) {
+        fail("write to outside of RAM at %d", address);
+    }
+    ram[address] = value;
+}
+
+static void push(int16_t value) {
+    wr(ram[0], value);
+    ram[0] = (int16_t)(ram[0] + 1);
+}
+
+static int16_t pop(void) {
+    ram[0] = (int16_t)(ram[0] - 1);
+    return rd(ram[0]);
+}
+
+static inline int16_t mul(int16_t x, int16_t y) {
+    return (int16_t)(uint16_t)((uint32_t)(uint16_t)x * (uint16_t)y);
+}
+
+static inline int16_t divide(int16_t x, int16_t y, int remainder) {
+    if (y == 0) {
+        fail("division by zero");
+    }
+    if (x == INT16_MIN && y == -1) {
+        return remainder ? 0 : INT16_MIN;
+    }
+    return remainder ? x % y : x / y;
+}
+
+static inline int16_t shift(int16_t x, int16_t y, int left) {
+    if (y <= 0) {
+        return x;
+    }
+    if (y >= 16) {
+        return 0;
+    }
+    return (int16_t)(uint16_t)(left ? (uint16_t)x << y : (uint16_t)x >> y);
+}
+
+static inline void call(int16_t return_address, int16_t args) {
+    push(return_address);
+    for (int pointer = 1; pointer <= 4; pointer++) {
+        push(ram[pointer]);
+    }
+    ram[2] = (int16_t)(ram[0] - 5 - args);
+    ram[1] = ram[0];
+}
+
+static inline int16_t return_from_function(void) {
+    int16_t frame = ram[1];
+    int16_t return_address = rd((int16_t)(frame - 5));
+    int16_t value = pop();
+    int16_t arg = ram[2];
+    wr(arg, value);
+    ram[0] = (int16_t)(arg + 1);
+    for (int pointer = 4; pointer >= 1; pointer--) {
+        ram[pointer] = rd((int16_t)(frame - 5 + pointer));
+    }
+    return return_address;
+}
+
+/* stops before running a command once max_steps have been run */
+#define STEP(n)                 \
+    if (steps == max_steps) {   \
+        return 0;               \
+    }                           \
+    steps++;                    \
+    current = n;
+"#;
+
+/// Reads the options, runs the program and prints or saves whatever was asked for
+const MAIN: &str = r#"
+static void write_screen(const char *path) {
+    FILE *file = fopen(path, "wb");
+    if (file == NULL) {
+        fprintf(stderr, "Could not write '%s'\n", path);
+        return;
+    }
+    fprintf(file, "P6\n512 256\n255\n");
+    for (int row = 0; row < 256; row++) {
+        for (int column = 0; column < 512; column++) {
+            int16_t word = ram[SCREEN + row * 32 + column / 16];
+            unsigned char colour = (word >> (column % 16)) & 1 ? 0 : 255;
+            unsigned char pixel[3] = {colour, colour, colour};
+            fwrite(pixel, 1, 3, file);
+        }
+    }
+    fclose(file);
+    printf("Wrote the screen to '%s'\n", path);
+}
+
+/* one character for every 4x4 block of pixels */
+static void print_screen(void) {
+    for (int row = 0; row < 256; row += 4) {
+        for (int column = 0; column < 512; column += 4) {
+            int set = 0;
+            for (int y = row; y < row + 4; y++) {
+                int16_t word = ram[SCREEN + y * 32 + column / 16];
+                set |= (word >> (column % 16)) & 0xf;
+            }
+            putchar(set ? '#' : ' ');
+        }
+        putchar('\n');
+    }
+}
+
+int main(int argc, char **argv) {
+    const char *screen = NULL;
+    int text = 0;
+    for (int i = 1; i < argc; i++) {
+        if (strcmp(argv[i], "--screen") == 0 && i + 1 < argc) {
+            screen = argv[++i];
+        } else if (strcmp(argv[i], "--text") == 0) {
+            text = 1;
+        } else if (strcmp(argv[i], "--keys") == 0 && i + 1 < argc) {
+            keys = argv[++i];
+        } else if (strcmp(argv[i], "--steps") == 0 && i + 1 < argc) {
+            max_steps = strtoull(argv[++i], NULL, 10);
+        } else if (strcmp(argv[i], "--ram") == 0 && i + 1 < argc) {
+            i++;
+        } else {
+            fprintf(stderr, "usage: %s [--steps N] [--keys TEXT] [--ram A[-B]]... [--screen FILE.ppm] [--text]\n", argv[0]);
+            return 2;
+        }
+    }
+
+    bootstrap();
+    if (run(START)) {
+        printf("Halted after %llu vm commands\n", (unsigned long long)steps);
+    } else {
+        printf("Stopped after %llu vm commands\n", (unsigned long long)steps);
+    }
+
+    for (int i = 1; i < argc; i++) {
+        if (strcmp(argv[i], "--ram") != 0 || i + 1 >= argc) {
+            continue;
+        }
+        char *end;
+        long start = strtol(argv[++i], &end, 10);
+        long last = *end == '-' ? strtol(end + 1, NULL, 10) : start;
+        for (long address = start; address <= last && address >= 0 && address < RAM_SIZE; address++) {
+            printf("RAM[%ld] = %d\n", address, ram[address]);
+        }
+    }
+    if (screen != NULL) {
+        write_screen(screen);
+    }
+    if (text) {
+        print_screen();
+    }
+    return 0;
+}
+"#;
+
+/// A C string literal
+fn c_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A C expression for an int16_t, which can't be written as a plain -32768 literal
+fn c_int(value: i16) -> String {
+    match value {
+        i16::MIN => String::from("INT16_MIN"),
+        _ => value.to_string(),
+    }
+}
+
+/// Turns the program into a single C file that runs it the same way the emulator does.
+/// Every command becomes a few statements in one big switch, vm labels and calls
+/// become C gotos and returns go back through the switch to the command after the call
+pub fn to_c(program: &[VmFile], bootstrap: &Bootstrap) -> String {
+    let emulator = Emulator::new(program);
+    let commands = &emulator.commands;
+
+    // where a goto or call can land, and where the switch can land
+    let mut targets = BTreeSet::new();
+    let mut cases = BTreeSet::from([0]);
+    for (index, command) in commands.iter().enumerate() {
+        match command {
+            Command::Goto(label) | Command::If(label) | Command::IfNot(label) => {
+                let target = emulator
+                    .labels
+                    .get(&(emulator.owners[index], label.clone()));
+                // a goto straight back to its own label stops the program instead
+                let stops = matches!(command, Command::Goto(_))
+                    && target.is_some_and(|target| target + 1 == index);
+                if let Some(target) = target.filter(|_| !stops) {
+                    targets.insert(*target);
+                }
+            }
+            Command::Call(name, _) => {
+                if let Some(start) = emulator.functions.get(name) {
+                    targets.insert(*start);
+                }
+                cases.insert(index + 1);
+            }
+            _ => {}
+        }
+    }
+    let start = if bootstrap.call_entry {
+        emulator.functions[&bootstrap.entry]
+    } else {
+        0
+    };
+    cases.insert(start);
+
+    let mut output = String::from(RUNTIME);
+    output += "\nconst char *const sources[] = {\n";
+    for (file, line) in &emulator.sources {
+        let source = format!("{}.vm:{}", emulator.file_names[*file], line);
+        writeln!(output, "    {},", c_string(&source)).unwrap();
+    }
+    output += "    \"\",\n};\n\n";
+
+    // the same pointers the emulator's bootstrap sets, SP included when nothing is called
+    output += "static void bootstrap(void) {\n";
+    let sp = Some(bootstrap.sp.unwrap_or(256));
+    let pointers = [
+        sp,
+        bootstrap.lcl,
+        bootstrap.arg,
+        bootstrap.this,
+        bootstrap.that,
+    ];
+    for (address, value) in pointers.iter().enumerate() {
+        if let Some(value) = value {
+            writeln!(output, "    ram[{}] = {};", address, c_int(*value as i16)).unwrap();
+        }
+    }
+    if bootstrap.call_entry {
+        output += "    call(-1, 0);\n";
+    }
+    output += "}\n\n";
+    writeln!(output, "#define START {}\n", start).unwrap();
+
+    output += "/* returns 1 once the program has stopped by itself */\n";
+    output += "static int run(int32_t pc) {\n";
+    output += "    int16_t x, y;\n";
+    output += "    (void)x;\n";
+    output += "    (void)y;\n";
+    output += "    for (;;) {\n";
+    output += "        switch (pc) {\n";
+    for (index, command) in commands.iter().enumerate() {
+        if cases.contains(&index) {
+            writeln!(output, "        case {}:", index).unwrap();
+        }
+        if targets.contains(&index) {
+            writeln!(output, "        c{}:", index).unwrap();
+        }
+        writeln!(output, "            STEP({}) /* {} */", index, command).unwrap();
+        for statement in translate(&emulator, index, command) {
+            writeln!(output, "            {}", statement).unwrap();
+        }
+    }
+    // running off the end stops the program
+    output += "            return 1;\n";
+    output += "        default:\n";
+    writeln!(
+        output,
+        "            if (pc < 0 || pc >= {}) {{",
+        commands.len()
+    )
+    .unwrap();
+    output += "                return 1;\n";
+    output += "            }\n";
+    output += "            fail(\"returned into the middle of a function at %d\", pc);\n";
+    output += "        }\n";
+    output += "    }\n";
+    output += "}\n";
+    output += MAIN;
+    output
+}
+
+/// The C statements for one command
+fn translate(emulator: &Emulator, index: usize, command: &Command) -> Vec<String> {
+    let fail = |format: &str, value: &str| format!("fail(\"{}\", {});", format, c_string(value));
+    let address = |segment: &str, offset: u16| {
+        let based = |pointer: u16| format!("(int16_t)(ram[{}] + {})", pointer, offset as i16);
+        match segment {
+            "local" => Some(based(1)),
+            "argument" => Some(based(2)),
+            "this" => Some(based(3)),
+            "that" => Some(based(4)),
+            "pointer" => Some(c_int(3 + offset as i16)),
+            "temp" => Some(c_int(5 + offset as i16)),
+            "static" => {
+                let (file, _) = emulator.sources[index];
+                Some(emulator.statics[&(file, offset)].to_string())
+            }
+            _ => None,
+        }
+    };
+    let jump = |label: &String| match emulator
+        .labels
+        .get(&(emulator.owners[index], label.clone()))
+    {
+        Some(target) => format!("goto c{};", target),
+        None => fail("unknown label '%s'", label),
+    };
+
+    match command {
+        Command::Push(segment, value) if segment == "constant" => {
+            vec![format!("push({});", c_int(*value as i16))]
+        }
+        Command::Push(segment, offset) => match address(segment, *offset) {
+            Some(address) => vec![format!("push(rd({}));", address)],
+            None => vec![fail("unknown segment '%s'", segment)],
+        },
+        Command::Pop(segment, offset) => match address(segment, *offset) {
+            Some(address) => vec![format!("x = {};", address), String::from("wr(x, pop());")],
+            None => vec![fail("unknown segment '%s'", segment)],
+        },
+        Command::Arithmetic(operator) if operator == "neg" => {
+            vec![String::from("push((int16_t)-pop());")]
+        }
+        Command::Arithmetic(operator) if operator == "not" => {
+            vec![String::from("push((int16_t)~pop());")]
+        }
+        Command::Arithmetic(operator) => {
+            let result = match operator.as_str() {
+                "add" => "(int16_t)(x + y)",
+                "sub" => "(int16_t)(x - y)",
+                "and" => "x & y",
+                "or" => "x | y",
+                "eq" => "-(x == y)",
+                "gt" => "-(x > y)",
+                "lt" => "-(x < y)",
+                "mul" => "mul(x, y)",
+                "div" => "divide(x, y, 0)",
+                "mod" => "divide(x, y, 1)",
+                "shl" => "shift(x, y, 1)",
+                "shr" => "shift(x, y, 0)",
+                _ => return vec![fail("unknown command '%s'", operator)],
+            };
+            vec![
+                String::from("y = pop();"),
+                String::from("x = pop();"),
+                format!("push({});", result),
+            ]
+        }
+        Command::Label(_) => vec![],
+        Command::Goto(label) => {
+            // `label END; goto END` is how programs stop
+            match emulator
+                .labels
+                .get(&(emulator.owners[index], label.clone()))
+            {
+                Some(target) if target + 1 == index => vec![String::from("return 1;")],
+                _ => vec![jump(label)],
+            }
+        }
+        Command::If(label) => vec![format!("if (pop() != 0) {}", jump(label))],
+        Command::IfNot(label) => vec![format!("if (pop() == 0) {}", jump(label))],
+        Command::Function(_, 0) => vec![],
+        Command::Function(_, locals) => {
+            vec![format!("for (int i = 0; i < {}; i++) push(0);", locals)]
+        }
+        Command::Call(name, args) => match emulator.functions.get(name) {
+            Some(start) => vec![
+                format!("call({}, {});", index + 1, args),
+                format!("goto c{};", start),
+            ],
+            None => vec![fail("call to undefined function '%s'", name)],
+        },
+        Command::Return => vec![
+            String::from("pc = return_from_function();"),
+            String::from("if (pc == -1) {"),
+            String::from("    return 1;"),
+            String::from("}"),
+            String::from("break;"),
+        ],
+    }
+}
+
+pub fn run_vm2c<P>(path: P, bootstrap: Bootstrap)
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    println!("Compiling '{}' to C", path.as_ref().display());
+
+    let Some(files) = valid_files(&path) else {
+        println!("Could not find any valid '.vm' files to work on.");
+        return;
+    };
+    let program: Vec<VmFile> = files.iter().map(parse_file).collect();
+    let emulator = Emulator::new(&program);
+    if bootstrap.call_entry && !emulator.functions.contains_key(&bootstrap.entry) {
+        println!(
+            "Could not find a '{}' function for the bootstrap code to call.",
+            bootstrap.entry
+        );
+        return;
+    }
+
+    let mut output_path = create_output_path(&path);
+    output_path.set_extension("c");
+    fs::write(&output_path, to_c(&program, &bootstrap)).unwrap();
+    println!("Saving C code to {:?}", output_path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_c() {
+        let program = vec![VmFile::new(
+            String::from("Sys"),
+            [
+                "function Sys.init 0",
+                "push constant 3",
+                "call Sys.double 1",
+                "pop static 0",
+                "label END",
+                "goto END",
+                "function Sys.double 1",
+                "push argument 0",
+                "push argument 0",
+                "add",
+                "return",
+            ]
+            .iter()
+            .map(|line| line.parse().unwrap())
+            .collect(),
+        )];
+        let output = to_c(&program, &Bootstrap::default());
+
+        assert!(output.contains("    ram[0] = 256;\n    call(-1, 0);\n"));
+        assert!(output.contains("#define START 0\n"));
+        assert!(output.contains("call(3, 1);\n            goto c6;\n"));
+        // the return lands back in the switch
+        assert!(output.contains("        case 3:\n            STEP(3) /* pop static 0 */\n"));
+        assert!(output.contains("            x = 16;\n"));
+        assert!(output.contains("STEP(5) /* goto END */\n            return 1;\n"));
+        assert!(output.contains("for (int i = 0; i < 1; i++) push(0);"));
+        assert!(output.contains("    \"Sys.vm:11\",\n"));
+    }
+
+    #[test]
+    fn test_to_c_without_entry_call() {
+        let program = vec![VmFile::new(
+            String::from("SimpleAdd"),
+            ["push constant 7", "push constant 8", "add"]
+                .iter()
+                .map(|line| line.parse().unwrap())
+                .collect(),
+        )];
+        let bootstrap = Bootstrap {
+            call_entry: false,
+            lcl: Some(300),
+            ..Default::default()
+        };
+        let output = to_c(&program, &bootstrap);
+        assert!(
+            output.contains(
+                "static void bootstrap(void) {\n    ram[0] = 256;\n    ram[1] = 300;\n}\n"
+            )
+        );
+
+        let bootstrap = Bootstrap {
+            sp: Some(400),
+            ..bootstrap
+        };
+        let output = to_c(&program, &bootstrap);
+        assert!(output.contains("    ram[0] = 400;\n    ram[1] = 300;\n}\n"));
+    }
+}
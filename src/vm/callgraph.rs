@@ -0,0 +1,389 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::vm::commands::Command;
+use crate::vm::optimizer::function_ranges;
+use crate::vm::parser::{VmFile, parse_file};
+use crate::vm::run::{create_output_path, valid_files};
+
+/// Which functions call which, built from the `function` and `call` commands
+#[derive(Debug, Default)]
+pub struct CallGraph {
+    /// Function name to the file it is defined in
+    pub files: BTreeMap<String, String>,
+    /// Caller to callee to the number of call sites
+    pub calls: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+#[derive(Debug, Serialize)]
+struct CallGraphJson<'a> {
+    entry: &'a str,
+    functions: Vec<FunctionJson<'a>>,
+    undefined: Vec<&'a String>,
+    cycles: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+struct FunctionJson<'a> {
+    name: &'a str,
+    file: Option<&'a String>,
+    calls: Vec<CallJson<'a>>,
+    defined: bool,
+    reachable: bool,
+    recursive: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct CallJson<'a> {
+    function: &'a str,
+    count: usize,
+}
+
+impl CallGraph {
+    pub fn new(program: &[VmFile]) -> Self {
+        let mut graph = CallGraph::default();
+        for file in program {
+            for (start, end) in function_ranges(&file.commands) {
+                let body = &file.commands[start..end];
+                if body.is_empty() {
+                    continue;
+                }
+                // code before the first function doesn't belong to anything that can be called
+                let caller = match body.first() {
+                    Some(Command::Function(name, _)) => name.clone(),
+                    _ => format!("{}.vm", file.name),
+                };
+                if matches!(body.first(), Some(Command::Function(..))) {
+                    graph
+                        .files
+                        .insert(caller.clone(), format!("{}.vm", file.name));
+                }
+
+                let callees = graph.calls.entry(caller).or_default();
+                for command in body {
+                    if let Command::Call(name, _) = command {
+                        *callees.entry(name.clone()).or_default() += 1;
+                    }
+                }
+            }
+        }
+        graph
+    }
+
+    /// Every function that is defined or called
+    pub fn functions(&self) -> BTreeSet<&String> {
+        self.calls
+            .iter()
+            .flat_map(|(caller, callees)| std::iter::once(caller).chain(callees.keys()))
+            .collect()
+    }
+
+    /// Functions that are called somewhere but never defined
+    pub fn undefined(&self) -> BTreeSet<&String> {
+        self.calls
+            .values()
+            .flat_map(|callees| callees.keys())
+            .filter(|name| !self.files.contains_key(*name))
+            .collect()
+    }
+
+    /// Functions that can be reached by following calls from the entry point
+    pub fn reachable(&self, entry: &str) -> BTreeSet<String> {
+        let mut reachable = BTreeSet::new();
+        let mut roots = vec![entry.to_string()];
+        // code outside of any function always runs
+        roots.extend(
+            self.calls
+                .keys()
+                .filter(|caller| !self.files.contains_key(*caller))
+                .cloned(),
+        );
+
+        while let Some(name) = roots.pop() {
+            if reachable.insert(name.clone())
+                && let Some(callees) = self.calls.get(&name)
+            {
+                roots.extend(callees.keys().cloned());
+            }
+        }
+        reachable
+    }
+
+    /// Groups of functions that can end up calling themselves, using Tarjan's
+    /// strongly connected components
+    pub fn cycles(&self) -> Vec<Vec<String>> {
+        let mut search = CycleSearch {
+            graph: self,
+            index: BTreeMap::new(),
+            low_link: BTreeMap::new(),
+            stack: vec![],
+            on_stack: BTreeSet::new(),
+            cycles: vec![],
+        };
+        for name in self.calls.keys() {
+            if !search.index.contains_key(name) {
+                search.visit(name);
+            }
+        }
+
+        let mut cycles = search.cycles;
+        for cycle in cycles.iter_mut() {
+            cycle.sort();
+        }
+        cycles.sort();
+        cycles
+    }
+
+    pub fn to_json(&self, entry: &str) -> String {
+        let reachable = self.reachable(entry);
+        let cycles = self.cycles();
+        let recursive: BTreeSet<&String> = cycles.iter().flatten().collect();
+
+        let functions = self
+            .functions()
+            .into_iter()
+            .map(|name| FunctionJson {
+                name,
+                file: self.files.get(name),
+                calls: self
+                    .calls
+                    .get(name)
+                    .into_iter()
+                    .flatten()
+                    .map(|(function, count)| CallJson {
+                        function,
+                        count: *count,
+                    })
+                    .collect(),
+                defined: self.files.contains_key(name),
+                reachable: reachable.contains(name),
+                recursive: recursive.contains(name),
+            })
+            .collect();
+
+        let json = CallGraphJson {
+            entry,
+            functions,
+            undefined: self.undefined().into_iter().collect(),
+            cycles,
+        };
+        serde_json::to_string_pretty(&json).unwrap()
+    }
+
+    /// Unreachable functions are dashed, undefined ones are red boxes and
+    /// recursive calls are orange
+    pub fn to_dot(&self, entry: &str) -> String {
+        let reachable = self.reachable(entry);
+        let undefined = self.undefined();
+        let cycles = self.cycles();
+        let cycle_of: BTreeMap<&String, usize> = cycles
+            .iter()
+            .enumerate()
+            .flat_map(|(i, cycle)| cycle.iter().map(move |name| (name, i)))
+            .collect();
+
+        let mut output = String::from("digraph callgraph {\n");
+        output += "    node [shape=ellipse];\n";
+        for name in self.functions() {
+            let mut attributes = vec![];
+            if undefined.contains(name) {
+                attributes.push("shape=box, color=red");
+            } else if !reachable.contains(name) {
+                attributes.push("style=dashed, color=gray");
+            }
+            if cycle_of.contains_key(name) {
+                attributes.push("penwidth=2, fontcolor=orange");
+            }
+            if attributes.is_empty() {
+                output += &format!("    \"{}\";\n", name);
+            } else {
+                output += &format!("    \"{}\" [{}];\n", name, attributes.join(", "));
+            }
+        }
+
+        for (caller, callees) in &self.calls {
+            for (callee, count) in callees {
+                let mut attributes = vec![];
+                if *count > 1 {
+                    attributes.push(format!("label=\"{}\"", count));
+                }
+                if cycle_of
+                    .get(caller)
+                    .is_some_and(|i| cycle_of.get(callee) == Some(i))
+                {
+                    attributes.push(String::from("color=orange"));
+                }
+                if attributes.is_empty() {
+                    output += &format!("    \"{}\" -> \"{}\";\n", caller, callee);
+                } else {
+                    output += &format!(
+                        "    \"{}\" -> \"{}\" [{}];\n",
+                        caller,
+                        callee,
+                        attributes.join(", ")
+                    );
+                }
+            }
+        }
+        output += "}\n";
+        output
+    }
+}
+
+struct CycleSearch<'a> {
+    graph: &'a CallGraph,
+    index: BTreeMap<&'a String, usize>,
+    low_link: BTreeMap<&'a String, usize>,
+    stack: Vec<&'a String>,
+    on_stack: BTreeSet<&'a String>,
+    cycles: Vec<Vec<String>>,
+}
+
+impl<'a> CycleSearch<'a> {
+    fn visit(&mut self, name: &'a String) {
+        let index = self.index.len();
+        self.index.insert(name, index);
+        self.low_link.insert(name, index);
+        self.stack.push(name);
+        self.on_stack.insert(name);
+
+        let graph = self.graph;
+        for callee in graph.calls.get(name).into_iter().flat_map(|c| c.keys()) {
+            if !self.index.contains_key(callee) {
+                self.visit(callee);
+                let low = self.low_link[name].min(self.low_link[callee]);
+                self.low_link.insert(name, low);
+            } else if self.on_stack.contains(callee) {
+                let low = self.low_link[name].min(self.index[callee]);
+                self.low_link.insert(name, low);
+            }
+        }
+
+        if self.low_link[name] != self.index[name] {
+            return;
+        }
+        let mut component = vec![];
+        while let Some(member) = self.stack.pop() {
+            self.on_stack.remove(member);
+            component.push(member.clone());
+            if member == name {
+                break;
+            }
+        }
+
+        let calls_itself = graph
+            .calls
+            .get(name)
+            .is_some_and(|callees| callees.contains_key(name));
+        if component.len() > 1 || calls_itself {
+            self.cycles.push(component);
+        }
+    }
+}
+
+pub fn run_callgraph<P>(path: P, entry: &str)
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    println!("Building the call graph for '{}'", path.as_ref().display());
+
+    let Some(files) = valid_files(&path) else {
+        println!("Could not find any valid '.vm' files to work on.");
+        return;
+    };
+    let program: Vec<VmFile> = files.iter().map(parse_file).collect();
+    let graph = CallGraph::new(&program);
+
+    if !graph.files.contains_key(entry) {
+        println!(
+            "'{}' isn't defined, so every function is unreachable",
+            entry
+        );
+    }
+    let reachable = graph.reachable(entry);
+    let unreachable: Vec<&String> = graph
+        .files
+        .keys()
+        .filter(|name| !reachable.contains(*name))
+        .collect();
+    if !unreachable.is_empty() {
+        println!("Unreachable from {}:", entry);
+        for name in unreachable {
+            println!("    {}", name);
+        }
+    }
+    for cycle in graph.cycles() {
+        println!("Recursive: {}", cycle.join(", "));
+    }
+    for name in graph.undefined() {
+        println!("Undefined: {}", name);
+    }
+
+    let mut output_path = create_output_path(&path);
+    output_path.set_extension("dot");
+    fs::write(&output_path, graph.to_dot(entry)).unwrap();
+    println!("Saving call graph to {:?}", output_path);
+
+    output_path.set_extension("callgraph.json");
+    fs::write(&output_path, graph.to_json(entry)).unwrap();
+    println!("Saving call graph to {:?}", output_path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(lines: &[&str]) -> Vec<VmFile> {
+        vec![VmFile::new(
+            String::from("Main"),
+            lines.iter().map(|line| line.parse().unwrap()).collect(),
+        )]
+    }
+
+    #[test]
+    fn test_call_graph() {
+        let graph = CallGraph::new(&program(&[
+            "function Sys.init 0",
+            "call Main.even 1",
+            "call Main.even 1",
+            "return",
+            "function Main.even 0",
+            "call Main.odd 1",
+            "return",
+            "function Main.odd 0",
+            "call Main.even 1",
+            "return",
+            "function Main.count 0",
+            "call Main.count 1",
+            "call Math.multiply 2",
+            "return",
+            "function Main.unused 0",
+            "return",
+        ]));
+
+        assert_eq!(graph.calls["Sys.init"]["Main.even"], 2);
+        assert_eq!(
+            graph.cycles(),
+            vec![
+                vec![String::from("Main.count")],
+                vec![String::from("Main.even"), String::from("Main.odd")]
+            ]
+        );
+        assert_eq!(
+            graph.undefined().into_iter().collect::<Vec<_>>(),
+            vec!["Math.multiply"]
+        );
+
+        let reachable = graph.reachable("Sys.init");
+        assert!(reachable.contains("Main.odd"));
+        assert!(!reachable.contains("Main.count"));
+        assert!(!reachable.contains("Main.unused"));
+
+        let dot = graph.to_dot("Sys.init");
+        assert!(dot.contains("\"Sys.init\" -> \"Main.even\" [label=\"2\"];"));
+        assert!(dot.contains("\"Math.multiply\" [shape=box, color=red];"));
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt::Display;
 use std::str::FromStr;
 
 pub enum CommandType {
@@ -7,6 +8,7 @@ pub enum CommandType {
     Label,
     Goto,
     If,
+    IfNot,
     Function,
     Return,
     Call,
@@ -24,6 +26,7 @@ impl FromStr for CommandType {
             "pop" => Ok(Self::Pop),
             "label" => Ok(Self::Label),
             "if-goto" => Ok(Self::If),
+            "if-not-goto" => Ok(Self::IfNot),
             "goto" => Ok(Self::Goto),
             "function" => Ok(Self::Function),
             "return" => Ok(Self::Return),
@@ -32,3 +35,102 @@ impl FromStr for CommandType {
         }
     }
 }
+
+/// A single parsed line of a .vm file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Arithmetic(String),
+    Push(String, u16),
+    Pop(String, u16),
+    Label(String),
+    Goto(String),
+    If(String),
+    /// Jumps when the popped value is false. Not part of the standard vm language: the
+    /// optimizer produces it and it is parsed back so optimized commands can be read again
+    IfNot(String),
+    Function(String, u16),
+    Return,
+    Call(String, u16),
+}
+
+impl Command {
+    pub fn push_constant(value: u16) -> Self {
+        Command::Push(String::from("constant"), value)
+    }
+
+    pub fn arithmetic(operator: &str) -> Self {
+        Command::Arithmetic(operator.to_string())
+    }
+
+    /// Whether execution can never carry on to the next command
+    pub fn is_unconditional_jump(&self) -> bool {
+        matches!(self, Command::Goto(_) | Command::Return)
+    }
+}
+
+impl FromStr for Command {
+    type Err = CommandParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens: Vec<&str> = s.split_whitespace().collect();
+        if tokens.is_empty() {
+            return Err(CommandParseError);
+        }
+
+        let argument = |i: usize| {
+            tokens
+                .get(i)
+                .map(|t| t.to_string())
+                .ok_or(CommandParseError)
+        };
+        let number = |i: usize| {
+            tokens
+                .get(i)
+                .and_then(|t| t.parse::<u16>().ok())
+                .ok_or(CommandParseError)
+        };
+
+        let command = match CommandType::from_str(tokens[0])? {
+            CommandType::Arithmetic => Command::Arithmetic(argument(0)?),
+            CommandType::Push => Command::Push(argument(1)?, number(2)?),
+            CommandType::Pop => Command::Pop(argument(1)?, number(2)?),
+            CommandType::Label => Command::Label(argument(1)?),
+            CommandType::Goto => Command::Goto(argument(1)?),
+            CommandType::If => Command::If(argument(1)?),
+            CommandType::IfNot => Command::IfNot(argument(1)?),
+            CommandType::Function => Command::Function(argument(1)?, number(2)?),
+            CommandType::Return => Command::Return,
+            CommandType::Call => Command::Call(argument(1)?, number(2)?),
+        };
+        Ok(command)
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::Arithmetic(operator) => write!(f, "{}", operator),
+            Command::Push(segment, index) => write!(f, "push {} {}", segment, index),
+            Command::Pop(segment, index) => write!(f, "pop {} {}", segment, index),
+            Command::Label(label) => write!(f, "label {}", label),
+            Command::Goto(label) => write!(f, "goto {}", label),
+            Command::If(label) => write!(f, "if-goto {}", label),
+            Command::IfNot(label) => write!(f, "if-not-goto {}", label),
+            Command::Function(name, locals) => write!(f, "function {} {}", name, locals),
+            Command::Return => write!(f, "return"),
+            Command::Call(name, args) => write!(f, "call {} {}", name, args),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_if_not_goto_round_trip() {
+        let command: Command = "if-not-goto WHILE_END0".parse().unwrap();
+        assert_eq!(command, Command::IfNot(String::from("WHILE_END0")));
+        assert_eq!(command.to_string(), "if-not-goto WHILE_END0");
+    }
+}
@@ -0,0 +1,141 @@
+use clap::ValueEnum;
+
+use crate::vm::parser::Parser;
+
+/// How `call`, `return` and the comparisons get turned into assembly
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CodeGen {
+    /// Every command is written out in full where it is used
+    Inline,
+    /// Commands jump to shared routines that are written out once
+    Compact,
+}
+
+/// Counts the instructions that end up in ROM, skipping labels and comments
+pub fn instruction_count(asm: &str) -> usize {
+    asm.lines()
+        .map(str::trim)
+        .filter(|line| !(line.is_empty() | line.starts_with("//") | line.starts_with("(")))
+        .count()
+}
+
+impl Parser {
+    /// Set up R13 (function), R14 (argument count) and D (return address)
+    /// then let $$CALL build the frame
+    pub fn compact_call(&mut self, fn_name: &str, arg_count: u16) {
+        match arg_count {
+            0 | 1 => {
+                self.output += "@R14\n";
+                self.output += &format!("M={}\n", arg_count);
+            }
+            _ => {
+                self.output += &format!("@{}\n", arg_count);
+                self.output += "D=A\n";
+                self.output += "@R14\n";
+                self.output += "M=D\n";
+            }
+        }
+        self.output += &format!("@{}\n", fn_name);
+        self.output += "D=A\n";
+        self.output += "@R13\n";
+        self.output += "M=D\n";
+
+        self.output += &format!("@{}$ret.{}\n", fn_name, self.caller_return_number);
+        self.output += "D=A\n";
+        self.output += "@$$CALL\n";
+        self.output += "0;JMP\n";
+        self.output += &format!("({}$ret.{})\n", fn_name, self.caller_return_number);
+
+        self.caller_return_number += 1;
+    }
+
+    /// Store where to come back to in R15 and jump to the shared comparison
+    pub fn compact_comparison(&mut self, comparison: &str) {
+        self.output += &format!("@RETURN_ADDRESS_{}\n", self.general_return_number);
+        self.output += "D=A\n";
+        self.output += "@R15\n";
+        self.output += "M=D\n";
+        self.output += &format!("@$${}\n", comparison.to_uppercase());
+        self.output += "0;JMP\n";
+        self.output += &format!("(RETURN_ADDRESS_{})\n", self.general_return_number);
+
+        self.general_return_number += 1;
+    }
+
+    /// Writes out the routines the compact call sites jump to
+    pub fn write_shared_routines(&mut self) {
+        // never fall through into the routines below
+        self.output += "($$HALT)\n";
+        self.output += "@$$HALT\n";
+        self.output += "0;JMP\n";
+
+        // D holds the return address
+        self.output += "($$CALL)\n";
+        self.push_d();
+        self.push_address_to_stack("LCL");
+        self.push_address_to_stack("ARG");
+        self.push_address_to_stack("THIS");
+        self.push_address_to_stack("THAT");
+
+        // ARG = SP - 5 - arg_count
+        self.output += "@SP\n";
+        self.output += "D=M\n";
+        self.output += "@5\n";
+        self.output += "D=D-A\n";
+        self.output += "@R14\n";
+        self.output += "D=D-M\n";
+        self.output += "@ARG\n";
+        self.output += "M=D\n";
+
+        // LCL = SP
+        self.output += "@SP\n";
+        self.output += "D=M\n";
+        self.output += "@LCL\n";
+        self.output += "M=D\n";
+
+        self.output += "@R13\n";
+        self.output += "A=M\n";
+        self.output += "0;JMP\n";
+
+        self.output += "($$RETURN)\n";
+        self.write_return();
+
+        for (comparison, jump) in [("EQ", "JEQ"), ("GT", "JGT"), ("LT", "JLT")] {
+            self.output += &format!("($${})\n", comparison);
+            self.get_values();
+            self.output += "D=M-D\n";
+            self.output += "@$$TRUE\n";
+            self.output += &format!("D;{}\n", jump);
+            self.output += "@$$FALSE\n";
+            self.output += "0;JMP\n";
+        }
+
+        self.output += "($$TRUE)\n";
+        self.output += "@SP\n";
+        self.output += "A=M\n";
+        self.output += "M=-1\n";
+        self.output += "@$$COMPARE_END\n";
+        self.output += "0;JMP\n";
+        self.output += "($$FALSE)\n";
+        self.output += "@SP\n";
+        self.output += "A=M\n";
+        self.output += "M=0\n";
+        self.output += "($$COMPARE_END)\n";
+        self.output += "@SP\n";
+        self.output += "M=M+1\n";
+        self.output += "@R15\n";
+        self.output += "A=M\n";
+        self.output += "0;JMP\n";
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instruction_count() {
+        let asm = "// comment\n(LOOP)\n@LOOP\n0;JMP\n\n";
+        assert_eq!(instruction_count(asm), 2);
+    }
+}
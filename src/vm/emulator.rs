@@ -0,0 +1,632 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::vm::bootstrap::Bootstrap;
+use crate::vm::commands::Command;
+use crate::vm::math::{evaluate_math, is_math};
+use crate::vm::parser::{VmFile, parse_file};
+use crate::vm::profiler::Profiler;
+use crate::vm::run::valid_files;
+
+pub const RAM_SIZE: usize = 32768;
+/// Return address pushed by the bootstrap, returning to it stops the program
+const HALT_ADDRESS: i16 = -1;
+
+/// Something the program did that the vm can't carry on from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmulatorError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for EmulatorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.vm:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+/// Runs vm commands directly, with the stack and segments laid out in RAM
+/// the same way the translated assembly does it
+pub struct Emulator {
+    pub ram: Vec<i16>,
+    /// Every command of every file, one after the other
+    pub commands: Vec<Command>,
+    /// Which file and line each command came from
+    pub sources: Vec<(usize, usize)>,
+    pub file_names: Vec<String>,
+    /// Where each function starts
+    pub functions: BTreeMap<String, usize>,
+    /// The function each command belongs to, by the index of its `function` command
+    pub owners: Vec<usize>,
+    /// (function start, label) to the index of the label
+    pub labels: BTreeMap<(usize, String), usize>,
+    /// (file, index) to the RAM address of the static variable
+    pub statics: BTreeMap<(usize, u16), usize>,
+    pub pc: usize,
+    pub steps: u64,
+    pub halted: bool,
+}
+
+impl Emulator {
+    pub fn new(program: &[VmFile]) -> Self {
+        let mut emulator = Emulator {
+            ram: vec![0; RAM_SIZE],
+            commands: vec![],
+            sources: vec![],
+            file_names: vec![],
+            functions: BTreeMap::new(),
+            owners: vec![],
+            labels: BTreeMap::new(),
+            statics: BTreeMap::new(),
+            pc: 0,
+            steps: 0,
+            halted: false,
+        };
+
+        let mut owner = 0;
+        for (file_index, file) in program.iter().enumerate() {
+            emulator.file_names.push(file.name.clone());
+            for (command, line) in file.commands.iter().zip(&file.lines) {
+                let index = emulator.commands.len();
+                match command {
+                    Command::Function(name, _) => {
+                        owner = index;
+                        emulator.functions.insert(name.clone(), index);
+                    }
+                    Command::Label(label) => {
+                        emulator.labels.insert((owner, label.clone()), index);
+                    }
+                    // statics get handed out from RAM 16 in the order they show up, like the assembler does
+                    Command::Push(segment, index) | Command::Pop(segment, index)
+                        if segment == "static" =>
+                    {
+                        let next = 16 + emulator.statics.len();
+                        emulator.statics.entry((file_index, *index)).or_insert(next);
+                    }
+                    _ => {}
+                }
+                emulator.commands.push(command.clone());
+                emulator.sources.push((file_index, *line));
+                emulator.owners.push(owner);
+            }
+        }
+        emulator
+    }
+
+    /// Sets up the pointers and calls the entry function, like the bootstrap code does
+    pub fn bootstrap(&mut self, bootstrap: &Bootstrap) -> Result<(), EmulatorError> {
+        // the stack starts where the bootstrap would put it even when nothing is called,
+        // at 0 the first push would overwrite SP itself
+        let sp = Some(bootstrap.sp.unwrap_or(256));
+        let pointers = [
+            sp,
+            bootstrap.lcl,
+            bootstrap.arg,
+            bootstrap.this,
+            bootstrap.that,
+        ];
+        for (address, value) in pointers.iter().enumerate() {
+            if let Some(value) = value {
+                self.ram[address] = *value as i16;
+            }
+        }
+
+        if bootstrap.call_entry {
+            self.call(&bootstrap.entry, 0, HALT_ADDRESS)?;
+        }
+        Ok(())
+    }
+
+    /// Runs until the program stops or `max_steps` commands have been run
+    pub fn run(&mut self, max_steps: u64) -> Result<(), EmulatorError> {
+        while !self.halted && self.steps < max_steps {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    pub fn step(&mut self) -> Result<(), EmulatorError> {
+        let Some(command) = self.commands.get(self.pc).cloned() else {
+            self.halted = true;
+            return Ok(());
+        };
+        self.steps += 1;
+        self.pc += 1;
+
+        match &command {
+            Command::Push(segment, index) => {
+                let value = match segment.as_str() {
+                    "constant" => *index as i16,
+                    _ => self.read(self.address(segment, *index)?)?,
+                };
+                self.push(value)?;
+            }
+            Command::Pop(segment, index) => {
+                let address = self.address(segment, *index)?;
+                let value = self.pop()?;
+                self.write(address, value)?;
+            }
+            Command::Arithmetic(operator) => self.arithmetic(operator)?,
+            Command::Label(_) => {}
+            Command::Goto(label) => {
+                let target = self.label(label)?;
+                // `label END; goto END` is how programs stop
+                if target + 2 == self.pc {
+                    self.halted = true;
+                }
+                self.pc = target;
+            }
+            Command::If(label) => {
+                if self.pop()? != 0 {
+                    self.pc = self.label(label)?;
+                }
+            }
+            Command::IfNot(label) => {
+                if self.pop()? == 0 {
+                    self.pc = self.label(label)?;
+                }
+            }
+            Command::Function(_, locals) => {
+                for _ in 0..*locals {
+                    self.push(0)?;
+                }
+            }
+            Command::Call(name, args) => {
+                let return_address = self.pc as i16;
+                self.call(name, *args, return_address)?;
+            }
+            Command::Return => self.return_from_function()?,
+        }
+        Ok(())
+    }
+
+    pub fn error(&self, message: String) -> EmulatorError {
+        let (file, line) = self
+            .sources
+            .get(self.pc.saturating_sub(1))
+            .copied()
+            .unwrap_or_default();
+        EmulatorError {
+            file: self.file_names.get(file).cloned().unwrap_or_default(),
+            line,
+            message,
+        }
+    }
+
+    pub fn read(&self, address: i16) -> Result<i16, EmulatorError> {
+        match self.ram.get(address as u16 as usize) {
+            Some(value) if address >= 0 => Ok(*value),
+            _ => Err(self.error(format!("read from outside of RAM at {}", address))),
+        }
+    }
+
+    pub fn write(&mut self, address: i16, value: i16) -> Result<(), EmulatorError> {
+        if address < 0 {
+            return Err(self.error(format!("write to outside of RAM at {}", address)));
+        }
+        self.ram[address as usize] = value;
+        Ok(())
+    }
+
+    pub fn push(&mut self, value: i16) -> Result<(), EmulatorError> {
+        let sp = self.ram[0];
+        self.write(sp, value)?;
+        self.ram[0] = sp.wrapping_add(1);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<i16, EmulatorError> {
+        let sp = self.ram[0].wrapping_sub(1);
+        self.ram[0] = sp;
+        self.read(sp)
+    }
+
+    fn address(&self, segment: &str, index: u16) -> Result<i16, EmulatorError> {
+        let index = index as i16;
+        let based = |pointer: usize| self.ram[pointer].wrapping_add(index);
+        let address = match segment {
+            "local" => based(1),
+            "argument" => based(2),
+            "this" => based(3),
+            "that" => based(4),
+            "pointer" => 3 + index,
+            "temp" => 5 + index,
+            "static" => {
+                let (file, _) = self.sources[self.pc - 1];
+                self.statics[&(file, index as u16)] as i16
+            }
+            _ => return Err(self.error(format!("unknown segment '{}'", segment))),
+        };
+        Ok(address)
+    }
+
+    fn label(&self, label: &str) -> Result<usize, EmulatorError> {
+        let owner = self.owners[self.pc - 1];
+        self.labels
+            .get(&(owner, label.to_string()))
+            .copied()
+            .ok_or_else(|| self.error(format!("unknown label '{}'", label)))
+    }
+
+    fn arithmetic(&mut self, operator: &str) -> Result<(), EmulatorError> {
+        let from_bool = |value: bool| if value { -1 } else { 0 };
+        if operator == "neg" || operator == "not" {
+            let y = self.pop()?;
+            return self.push(if operator == "neg" {
+                y.wrapping_neg()
+            } else {
+                !y
+            });
+        }
+
+        let y = self.pop()?;
+        let x = self.pop()?;
+        let result = match operator {
+            "add" => x.wrapping_add(y),
+            "sub" => x.wrapping_sub(y),
+            "and" => x & y,
+            "or" => x | y,
+            "eq" => from_bool(x == y),
+            "gt" => from_bool(x > y),
+            "lt" => from_bool(x < y),
+            _ if is_math(operator) => evaluate_math(operator, x, y)
+                .ok_or_else(|| self.error(String::from("division by zero")))?,
+            _ => return Err(self.error(format!("unknown command '{}'", operator))),
+        };
+        self.push(result)
+    }
+
+    /// Saves the frame and jumps into the function, the arguments are already on the stack
+    fn call(&mut self, name: &str, args: u16, return_address: i16) -> Result<(), EmulatorError> {
+        let Some(start) = self.functions.get(name).copied() else {
+            return Err(self.error(format!("call to undefined function '{}'", name)));
+        };
+
+        self.push(return_address)?;
+        for pointer in 1..=4 {
+            self.push(self.ram[pointer])?;
+        }
+        self.ram[2] = self.ram[0].wrapping_sub(5 + args as i16);
+        self.ram[1] = self.ram[0];
+        self.pc = start;
+        Ok(())
+    }
+
+    fn return_from_function(&mut self) -> Result<(), EmulatorError> {
+        let frame = self.ram[1];
+        let return_address = self.read(frame.wrapping_sub(5))?;
+        let value = self.pop()?;
+        let arg = self.ram[2];
+        self.write(arg, value)?;
+        self.ram[0] = arg.wrapping_add(1);
+        for pointer in (1..=4).rev() {
+            self.ram[pointer] = self.read(frame.wrapping_sub(5 - pointer as i16))?;
+        }
+
+        if return_address == HALT_ADDRESS {
+            self.halted = true;
+        }
+        self.pc = return_address as u16 as usize;
+        Ok(())
+    }
+}
+
+/// Parses "256" or "256-260" into the addresses to print
+pub fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let start = start.trim().parse().ok()?;
+    let end = end.trim().parse().ok()?;
+    (start <= end && end < RAM_SIZE).then_some((start, end))
+}
+
+pub fn run_emulator<P>(
+    path: P,
+    bootstrap: Bootstrap,
+    max_steps: u64,
+    ram: &[String],
+    profile: bool,
+    folded: Option<PathBuf>,
+) where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    println!("Emulating '{}'", path.as_ref().display());
+
+    let Some(files) = valid_files(&path) else {
+        println!("Could not find any valid '.vm' files to work on.");
+        return;
+    };
+    let mut ranges = vec![];
+    for range in ram {
+        match parse_range(range) {
+            Some(range) => ranges.push(range),
+            None => {
+                println!("'{}' isn't a RAM address or range like 256-260", range);
+                return;
+            }
+        }
+    }
+
+    let program: Vec<VmFile> = files.iter().map(parse_file).collect();
+    let mut emulator = Emulator::new(&program);
+    let profiling = profile || folded.is_some();
+    let mut profiler = None;
+    let result = emulator.bootstrap(&bootstrap).and_then(|_| {
+        if !profiling {
+            return emulator.run(max_steps);
+        }
+        let entry = bootstrap.call_entry.then_some(bootstrap.entry.as_str());
+        let profiler = profiler.insert(Profiler::new(&emulator, entry));
+        profiler.run(&mut emulator, max_steps)
+    });
+
+    match result {
+        Err(error) => println!("Error: {}", error),
+        Ok(_) if emulator.halted => println!("Halted after {} vm commands", emulator.steps),
+        Ok(_) => println!("Stopped after {} vm commands", emulator.steps),
+    }
+    for (start, end) in ranges {
+        for address in start..=end {
+            println!("RAM[{}] = {}", address, emulator.ram[address]);
+        }
+    }
+
+    let Some(profiler) = profiler else {
+        return;
+    };
+    if profile {
+        print!("{}", profiler.report());
+    }
+    if let Some(folded) = folded {
+        match fs::write(&folded, profiler.folded_stacks()) {
+            Ok(_) => println!("Wrote folded stacks to '{}'", folded.display()),
+            Err(error) => println!("Could not write '{}': {}", folded.display(), error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::cpu::Cpu;
+    use crate::vm::parser::Parser;
+
+    fn file(name: &str, lines: &[&str]) -> VmFile {
+        VmFile::new(
+            String::from(name),
+            lines.iter().map(|line| line.parse().unwrap()).collect(),
+        )
+    }
+
+    #[test]
+    fn test_emulator() {
+        let program = vec![
+            file(
+                "Main",
+                &[
+                    "function Main.triangle 1",
+                    "label LOOP",
+                    "push argument 0",
+                    "if-goto ADD",
+                    "push local 0",
+                    "return",
+                    "label ADD",
+                    "push local 0",
+                    "push argument 0",
+                    "add",
+                    "pop local 0",
+                    "push argument 0",
+                    "push constant 1",
+                    "sub",
+                    "pop argument 0",
+                    "goto LOOP",
+                ],
+            ),
+            file(
+                "Sys",
+                &[
+                    "function Sys.init 0",
+                    "push constant 10",
+                    "call Main.triangle 1",
+                    "push constant 3",
+                    "mul",
+                    "pop static 0",
+                    "label END",
+                    "goto END",
+                ],
+            ),
+        ];
+
+        let mut emulator = Emulator::new(&program);
+        emulator.bootstrap(&Bootstrap::default()).unwrap();
+        emulator.run(10_000).unwrap();
+
+        assert!(emulator.halted);
+        assert_eq!(emulator.ram[16], 165);
+        assert_eq!(emulator.ram[0], 261);
+    }
+
+    #[test]
+    fn test_emulator_without_entry_call() {
+        let program = vec![file(
+            "SimpleAdd",
+            &["push constant 7", "push constant 8", "add", "pop local 0"],
+        )];
+
+        let mut emulator = Emulator::new(&program);
+        let bootstrap = Bootstrap {
+            call_entry: false,
+            lcl: Some(300),
+            ..Default::default()
+        };
+        emulator.bootstrap(&bootstrap).unwrap();
+        emulator.run(100).unwrap();
+        assert!(emulator.halted);
+        assert_eq!(emulator.ram[0], 256);
+        assert_eq!(emulator.ram[300], 15);
+
+        let mut emulator = Emulator::new(&program);
+        let bootstrap = Bootstrap {
+            sp: Some(400),
+            ..bootstrap
+        };
+        emulator.bootstrap(&bootstrap).unwrap();
+        emulator.run(100).unwrap();
+        assert_eq!(emulator.ram[0], 400);
+        assert_eq!(emulator.ram[300], 15);
+    }
+
+    #[test]
+    fn test_emulator_matches_translation() {
+        let program = vec![
+            file(
+                "Main",
+                &[
+                    // that[i] = 2 * i - 3 for i < argument 1, from the address in argument 0
+                    "function Main.fill 2",
+                    "push constant 0",
+                    "pop local 0",
+                    "label LOOP",
+                    "push local 0",
+                    "push argument 1",
+                    "lt",
+                    "not",
+                    "if-goto DONE",
+                    "push local 0",
+                    "push local 0",
+                    "add",
+                    "push constant 3",
+                    "sub",
+                    "pop local 1",
+                    "push argument 0",
+                    "push local 0",
+                    "add",
+                    "pop pointer 1",
+                    "push local 1",
+                    "pop that 0",
+                    "push local 0",
+                    "push constant 1",
+                    "add",
+                    "pop local 0",
+                    "goto LOOP",
+                    "label DONE",
+                    "push local 1",
+                    "pop static 0",
+                    "push argument 1",
+                    "return",
+                ],
+            ),
+            file(
+                "Sys",
+                &[
+                    "function Sys.init 0",
+                    "push constant 3000",
+                    "push constant 10",
+                    "call Main.fill 2",
+                    "pop static 0",
+                    "push constant 4000",
+                    "pop pointer 0",
+                    "push constant 5",
+                    "pop this 2",
+                    "push this 2",
+                    "push constant 5",
+                    "eq",
+                    "pop static 1",
+                    "push constant 7",
+                    "push constant 9",
+                    "gt",
+                    "pop static 2",
+                    "push constant 7",
+                    "neg",
+                    "push constant 9",
+                    "lt",
+                    "pop static 3",
+                    "push constant 12",
+                    "push constant 10",
+                    "and",
+                    "pop temp 6",
+                    "push constant 12",
+                    "push constant 3",
+                    "or",
+                    "not",
+                    "pop temp 7",
+                    "label END",
+                    "goto END",
+                ],
+            ),
+        ];
+
+        let mut emulator = Emulator::new(&program);
+        emulator.bootstrap(&Bootstrap::default()).unwrap();
+        emulator.run(10_000).unwrap();
+        assert!(emulator.halted);
+
+        let mut parser = Parser::new(5);
+        parser.bootstrap(&Bootstrap::default());
+        for file in &program {
+            parser.translate_file(file, false);
+        }
+        let mut cpu = Cpu::from_asm(&parser.output);
+        cpu.run(100_000);
+        assert!(cpu.halted);
+
+        // Main's static, then Sys's
+        assert_eq!(emulator.ram[16..21], [15, 10, -1, 0, -1]);
+        assert_eq!(emulator.ram[11..13], [8, -16]);
+        assert_eq!(
+            emulator.ram[3000..3010],
+            [-3, -1, 1, 3, 5, 7, 9, 11, 13, 15]
+        );
+        assert_eq!(emulator.ram[4002], 5);
+        // the pointers and temp match the assembly, past them it has its own scratch
+        // registers and variables, and saves rom addresses rather than command indexes
+        assert_eq!(emulator.ram[..13], cpu.ram[..13]);
+        assert_eq!(emulator.ram[3000..4003], cpu.ram[3000..4003]);
+    }
+
+    #[test]
+    fn test_emulator_errors() {
+        let program = vec![file(
+            "Sys",
+            &[
+                "function Sys.init 0",
+                "push constant 1",
+                "push constant 0",
+                "div",
+                "return",
+            ],
+        )];
+
+        let mut emulator = Emulator::new(&program);
+        emulator.bootstrap(&Bootstrap::default()).unwrap();
+        let error = emulator.run(100).unwrap_err();
+        assert_eq!(error.to_string(), "Sys.vm:4: division by zero");
+
+        for (lines, message) in [
+            (
+                &["function Sys.init 0", "call Main.missing 0"][..],
+                "Sys.vm:2: call to undefined function 'Main.missing'",
+            ),
+            (
+                &["function Sys.init 0", "goto NOWHERE"][..],
+                "Sys.vm:2: unknown label 'NOWHERE'",
+            ),
+            (
+                &[
+                    "function Sys.init 0",
+                    "push constant 1",
+                    "neg",
+                    "pop pointer 1",
+                    "push that 0",
+                ][..],
+                "Sys.vm:5: read from outside of RAM at -1",
+            ),
+        ] {
+            let mut emulator = Emulator::new(&[file("Sys", lines)]);
+            emulator.bootstrap(&Bootstrap::default()).unwrap();
+            assert_eq!(emulator.run(100).unwrap_err().to_string(), message);
+        }
+    }
+}
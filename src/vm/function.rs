@@ -1,7 +1,8 @@
+use crate::vm::compact::CodeGen;
 use crate::vm::parser::Parser;
 
 impl Parser {
-    fn push_address_to_stack(&mut self, address: &str) {
+    pub fn push_address_to_stack(&mut self, address: &str) {
         self.output += &format!("@{}\n", address);
         self.output += "D=M\n";
 
@@ -17,9 +18,9 @@ impl Parser {
         self.output += "D=M\n";
     }
 
-    pub fn handle_function(&mut self, tokens: Vec<&str>) {
-        let fn_name = tokens[1];
-        let local_variables = tokens[2].parse::<u32>().unwrap();
+    pub fn handle_function(&mut self, fn_name: &str, local_variables: u16) {
+        // labels inside this function are scoped to it
+        self.function_name = fn_name.to_string();
 
         // (file_name.function_Name)
         self.output += &format!("({})\n", fn_name);
@@ -33,9 +34,15 @@ impl Parser {
         }
     }
 
-    pub fn handle_call(&mut self, tokens: Vec<&str>) {
-        let fn_name = tokens[1];
-        let arg_count = tokens[2].parse::<u32>().unwrap();
+    pub fn handle_call(&mut self, fn_name: &str, arg_count: u16) {
+        if self.tail_call {
+            self.handle_tail_call(fn_name, arg_count);
+            return;
+        }
+        if self.code_gen == CodeGen::Compact {
+            self.compact_call(fn_name, arg_count);
+            return;
+        }
 
         // generate a label and push to stack
         self.output += &format!("@{}$ret.{}\n", fn_name, self.caller_return_number);
@@ -75,6 +82,16 @@ impl Parser {
     /// Push local to stack
     /// then go to return address
     pub fn handle_return(&mut self) {
+        if self.code_gen == CodeGen::Compact {
+            self.output += "@$$RETURN\n";
+            self.output += "0;JMP\n";
+            return;
+        }
+
+        self.write_return();
+    }
+
+    pub fn write_return(&mut self) {
         // frame = LCL - temp variable
         self.output += "@LCL\n";
         self.output += "D=M\n";
@@ -4,8 +4,7 @@ impl Parser {
     /// Pop topmost value off the stack
     /// if it is true
     /// jump to the label
-    pub fn handle_if_goto(&mut self, tokens: Vec<&str>) {
-        let label = tokens[1];
+    pub fn handle_if_goto(&mut self, label: &str) {
         // get top-most value off stack
         self.pop_stack();
         // value now stored in D
@@ -15,4 +14,14 @@ impl Parser {
         self.output += &format!("@{}${}\n", self.function_name, label);
         self.output += "D;JNE\n";
     }
+
+    /// Pop topmost value off the stack
+    /// if it is false
+    /// jump to the label
+    pub fn handle_if_not_goto(&mut self, label: &str) {
+        self.pop_stack();
+
+        self.output += &format!("@{}${}\n", self.function_name, label);
+        self.output += "D;JEQ\n";
+    }
 }
@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+
+use crate::vm::commands::Command;
+use crate::vm::optimizer::function_ranges;
+use crate::vm::parser::VmFile;
+use crate::vm::verify::verify;
+
+/// Inlined functions keep their values in temp 1-7 and leave temp 0 to the caller
+const FIRST_TEMP: u16 = 1;
+const TEMP_SLOTS: u16 = 7;
+
+/// A function small and simple enough to be copied into its callers
+#[derive(Debug)]
+struct Inlinable {
+    file: String,
+    /// Statics are named after the file, so these can only go into callers in the same file
+    uses_statics: bool,
+    locals: u16,
+    /// Highest argument index used, plus one
+    arguments: u16,
+    /// The pointer entries the function writes, which need restoring afterwards
+    pointers: Vec<u16>,
+    body: Vec<Command>,
+}
+
+/// Replaces calls to small functions with the function's own commands.
+/// Only functions that make no calls of their own, pass the stack verifier, have
+/// at most `threshold` commands and fit their arguments and locals in temp 1-7 are
+/// inlined, and only into functions that don't use temp 1-7 themselves.
+/// Returns how many calls were replaced
+pub fn inline(files: &mut [VmFile], threshold: usize) -> usize {
+    let inlinable = find_inlinable(files, threshold);
+    let mut inlined = 0;
+
+    for file in files.iter_mut() {
+        let mut commands = vec![];
+        let mut lines = vec![];
+        let callers = function_ranges(&file.commands)
+            .into_iter()
+            .flat_map(|(start, end)| {
+                // a value the caller keeps in temp 1-7 wouldn't survive an inlined call
+                let keeps_temps = file.commands[start..end].iter().any(uses_inline_temps);
+                std::iter::repeat_n(keeps_temps, end - start)
+            });
+        for ((command, line), keeps_temps) in file.commands.iter().zip(&file.lines).zip(callers) {
+            let callee = match command {
+                Command::Call(name, args) if !keeps_temps => inlinable
+                    .get(name)
+                    .filter(|callee| {
+                        callee.arguments <= *args
+                            && (!callee.uses_statics || callee.file == file.name)
+                    })
+                    .map(|callee| (callee, *args)),
+                _ => None,
+            };
+            let Some((callee, args)) = callee else {
+                commands.push(command.clone());
+                lines.push(*line);
+                continue;
+            };
+
+            let expanded = expand(callee, args, inlined);
+            lines.extend(std::iter::repeat_n(*line, expanded.len()));
+            commands.extend(expanded);
+            inlined += 1;
+        }
+        file.commands = commands;
+        file.lines = lines;
+    }
+    inlined
+}
+
+/// Whether the command works with the temps inlined functions keep their values in
+fn uses_inline_temps(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Push(segment, index) | Command::Pop(segment, index)
+            if segment == "temp" && *index >= FIRST_TEMP
+    )
+}
+
+fn find_inlinable(files: &[VmFile], threshold: usize) -> BTreeMap<String, Inlinable> {
+    let mut inlinable = BTreeMap::new();
+    for file in files {
+        for (start, end) in function_ranges(&file.commands) {
+            let Some(Command::Function(name, locals)) = file.commands.get(start) else {
+                continue;
+            };
+            let body = &file.commands[start + 1..end];
+            if body.len() > threshold {
+                continue;
+            }
+
+            let mut arguments = 0;
+            let mut pointers = vec![];
+            let mut simple = true;
+            let mut uses_statics = false;
+            for command in body {
+                match command {
+                    Command::Call(..) => simple = false,
+                    Command::Push(segment, _) | Command::Pop(segment, _) if segment == "temp" => {
+                        simple = false
+                    }
+                    Command::Push(segment, index) | Command::Pop(segment, index)
+                        if segment == "argument" =>
+                    {
+                        arguments = arguments.max(index + 1)
+                    }
+                    Command::Pop(segment, index)
+                        if segment == "pointer" && !pointers.contains(index) =>
+                    {
+                        pointers.push(*index)
+                    }
+                    Command::Push(segment, _) | Command::Pop(segment, _) if segment == "static" => {
+                        uses_statics = true
+                    }
+                    _ => {}
+                }
+            }
+            let slots = arguments as u32 + *locals as u32 + pointers.len() as u32;
+            if !simple || slots > TEMP_SLOTS as u32 {
+                continue;
+            }
+
+            let function = VmFile::new(file.name.clone(), file.commands[start..end].to_vec());
+            if !verify(&[function]).is_empty() {
+                continue;
+            }
+
+            inlinable.insert(
+                name.clone(),
+                Inlinable {
+                    file: file.name.clone(),
+                    uses_statics,
+                    locals: *locals,
+                    arguments,
+                    pointers,
+                    body: body.to_vec(),
+                },
+            );
+        }
+    }
+    inlinable
+}
+
+/// The commands that stand in for one call. Arguments go to temp 1 onwards, then
+/// the locals, then the saved pointers
+fn expand(callee: &Inlinable, args: u16, site: usize) -> Vec<Command> {
+    let temp = |slot: u16| Command::Pop(String::from("temp"), FIRST_TEMP + slot);
+    let local_base = callee.arguments;
+    let pointer_base = local_base + callee.locals;
+    let end_label = format!("INLINE_{}.END", site);
+
+    let mut commands = vec![];
+    // the arguments are on the stack with the last one on top. Any the callee
+    // never reads are dropped into temp 1, which the caller doesn't use
+    for index in (0..args).rev() {
+        if index < callee.arguments {
+            commands.push(temp(index));
+        } else {
+            commands.push(temp(0));
+        }
+    }
+    for local in 0..callee.locals {
+        commands.push(Command::push_constant(0));
+        commands.push(temp(local_base + local));
+    }
+    for (slot, pointer) in callee.pointers.iter().enumerate() {
+        commands.push(Command::Push(String::from("pointer"), *pointer));
+        commands.push(temp(pointer_base + slot as u16));
+    }
+
+    let rename = |segment: &String, index: u16| match segment.as_str() {
+        "argument" => (String::from("temp"), FIRST_TEMP + index),
+        "local" => (String::from("temp"), FIRST_TEMP + local_base + index),
+        _ => (segment.clone(), index),
+    };
+    let label = |label: &String| format!("INLINE_{}.{}", site, label);
+    let mut jumps_to_end = false;
+    for (i, command) in callee.body.iter().enumerate() {
+        let renamed = match command {
+            Command::Push(segment, index) => {
+                let (segment, index) = rename(segment, *index);
+                Command::Push(segment, index)
+            }
+            Command::Pop(segment, index) => {
+                let (segment, index) = rename(segment, *index);
+                Command::Pop(segment, index)
+            }
+            Command::Label(name) => Command::Label(label(name)),
+            Command::Goto(name) => Command::Goto(label(name)),
+            Command::If(name) => Command::If(label(name)),
+            Command::IfNot(name) => Command::IfNot(label(name)),
+            // the return value is already on top of the stack
+            Command::Return if i + 1 == callee.body.len() => continue,
+            Command::Return => {
+                jumps_to_end = true;
+                Command::Goto(end_label.clone())
+            }
+            other => other.clone(),
+        };
+        commands.push(renamed);
+    }
+    if jumps_to_end {
+        commands.push(Command::Label(end_label));
+    }
+
+    for (slot, pointer) in callee.pointers.iter().enumerate() {
+        commands.push(Command::Push(
+            String::from("temp"),
+            FIRST_TEMP + pointer_base + slot as u16,
+        ));
+        commands.push(Command::Pop(String::from("pointer"), *pointer));
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::vm::bootstrap::Bootstrap;
+    use crate::vm::emulator::Emulator;
+
+    fn commands(lines: &[&str]) -> Vec<Command> {
+        lines
+            .iter()
+            .map(|line| Command::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_inline_getter() {
+        let mut files = vec![VmFile::new(
+            String::from("Point"),
+            commands(&[
+                "function Point.getX 0",
+                "push argument 0",
+                "pop pointer 0",
+                "push this 0",
+                "return",
+                "function Point.main 0",
+                "push local 0",
+                "call Point.getX 1",
+                "return",
+            ]),
+        )];
+
+        assert_eq!(inline(&mut files, 10), 1);
+        assert_eq!(
+            files[0].commands[5..],
+            commands(&[
+                "function Point.main 0",
+                "push local 0",
+                "pop temp 1",
+                "push pointer 0",
+                "pop temp 2",
+                "push temp 1",
+                "pop pointer 0",
+                "push this 0",
+                "push temp 2",
+                "pop pointer 0",
+                "return",
+            ])
+        );
+        assert!(verify(&files).is_empty());
+    }
+
+    #[test]
+    fn test_skip_calls_and_large_functions() {
+        let mut files = vec![VmFile::new(
+            String::from("Main"),
+            commands(&[
+                "function Main.twice 0",
+                "push argument 0",
+                "call Main.twice 1",
+                "return",
+                "function Main.big 2",
+                "push argument 0",
+                "pop local 0",
+                "push local 0",
+                "return",
+                "function Main.main 0",
+                "push constant 1",
+                "call Main.twice 1",
+                "call Main.big 1",
+                "return",
+            ]),
+        )];
+
+        assert_eq!(inline(&mut files, 3), 0);
+        assert_eq!(inline(&mut files, 4), 1);
+    }
+
+    #[test]
+    fn test_caller_temps_survive() {
+        let program = [
+            "function Main.first 0",
+            "push argument 0",
+            "return",
+            // keeps 11 in temp 1 across the call, so it can't be inlined here
+            "function Main.keepTemp1 0",
+            "push constant 11",
+            "pop temp 1",
+            "push constant 5",
+            "push constant 6",
+            "call Main.first 2",
+            "push temp 1",
+            "add",
+            "return",
+            // temp 0 is the caller's, even with an argument nobody reads
+            "function Main.keepTemp0 0",
+            "push constant 20",
+            "pop temp 0",
+            "push constant 5",
+            "push constant 6",
+            "call Main.first 2",
+            "push temp 0",
+            "add",
+            "return",
+            "function Sys.init 0",
+            "call Main.keepTemp1 0",
+            "pop static 0",
+            "call Main.keepTemp0 0",
+            "pop static 1",
+            "label END",
+            "goto END",
+        ];
+        let mut files = vec![VmFile::new(String::from("Main"), commands(&program))];
+
+        assert_eq!(inline(&mut files, 10), 1);
+        assert_eq!(files[0].commands[3..12], commands(&program[3..12]));
+
+        let mut emulator = Emulator::new(&files);
+        emulator.bootstrap(&Bootstrap::default()).unwrap();
+        emulator.run(1000).unwrap();
+        assert!(emulator.halted);
+        assert_eq!(emulator.ram[16..18], [16, 25]);
+    }
+}
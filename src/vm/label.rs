@@ -1,8 +1,7 @@
 use crate::vm::parser::Parser;
 
 impl Parser {
-    pub fn handle_label(&mut self, tokens: Vec<&str>) {
-        let label_name = tokens[1];
+    pub fn handle_label(&mut self, label_name: &str) {
         self.output += &format!("({}${})\n", self.function_name, label_name);
     }
 }
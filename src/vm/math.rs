@@ -0,0 +1,507 @@
+use crate::vm::parser::Parser;
+
+/// The arithmetic commands that go beyond the standard vm language. They all pop
+/// y and x and push a single result, like `add`
+pub const MATH_COMMANDS: [&str; 5] = ["mul", "div", "mod", "shl", "shr"];
+
+pub fn is_math(operator: &str) -> bool {
+    MATH_COMMANDS.contains(&operator)
+}
+
+/// What the extended commands compute, with 16 bit wrap around. Division truncates
+/// towards zero and `mod` takes the sign of x. `shr` is a logical shift, and shifts
+/// by 16 or more leave nothing behind. Returns None when dividing by zero
+pub fn evaluate_math(operator: &str, x: i16, y: i16) -> Option<i16> {
+    let shift = |shifted: fn(u16, u32) -> u16| match y {
+        ..=0 => x,
+        16.. => 0,
+        _ => shifted(x as u16, y as u32) as i16,
+    };
+    match operator {
+        "mul" => Some(x.wrapping_mul(y)),
+        "div" if y != 0 => Some(x.wrapping_div(y)),
+        "mod" if y != 0 => Some(x.wrapping_rem(y)),
+        "shl" => Some(shift(|x, y| x << y)),
+        "shr" => Some(shift(|x, y| x >> y)),
+        _ => None,
+    }
+}
+
+impl Parser {
+    /// Store where to come back to in R15 and jump to the shared routine for the command,
+    /// which then gets written out with the rest of the math routines
+    pub fn handle_math(&mut self, operator: &str) {
+        self.math_routines.insert(operator.to_string());
+
+        self.output += &format!("@RETURN_ADDRESS_{}\n", self.general_return_number);
+        self.output += "D=A\n";
+        self.output += "@R15\n";
+        self.output += "M=D\n";
+        self.output += &format!("@$${}\n", operator.to_uppercase());
+        self.output += "0;JMP\n";
+        self.output += &format!("(RETURN_ADDRESS_{})\n", self.general_return_number);
+
+        self.general_return_number += 1;
+    }
+
+    /// `push constant c; shl` with the value in D is just c doublings
+    pub fn constant_shift_left(&mut self, constant: u16) {
+        if constant >= 16 {
+            self.output += "D=0\n";
+            return;
+        }
+        if constant > 0 {
+            self.output += "@R13\n";
+        }
+        for _ in 0..constant {
+            self.output += "M=D\n";
+            self.output += "D=D+M\n";
+        }
+    }
+
+    /// Writes out the routines for the extended commands that were used.
+    /// Each one takes x and y off the stack, leaves the result in their place
+    /// and jumps back to R15
+    pub fn write_math_routines(&mut self) {
+        if self.math_routines.is_empty() {
+            return;
+        }
+
+        // never fall through into the routines below
+        self.output += "($$MATH_HALT)\n";
+        self.output += "@$$MATH_HALT\n";
+        self.output += "0;JMP\n";
+
+        let routines = self.math_routines.clone();
+        if routines.contains("mul") {
+            self.write_multiply();
+        }
+        if routines.contains("div") | routines.contains("mod") {
+            self.write_divide(routines.contains("div"), routines.contains("mod"));
+        }
+        if routines.contains("shl") {
+            self.write_shift_left();
+        }
+        if routines.contains("shr") {
+            self.write_shift_right();
+        }
+    }
+
+    /// Pops y into MATH_B and leaves A pointing at x
+    fn math_operands(&mut self) {
+        self.output += "@SP\n";
+        self.output += "AM=M-1\n";
+        self.output += "D=M\n";
+        self.output += "@MATH_B\n";
+        self.output += "M=D\n";
+        self.output += "@SP\n";
+        self.output += "A=M-1\n";
+    }
+
+    /// Replaces x with D and goes back to the caller
+    fn math_result(&mut self) {
+        self.output += "@SP\n";
+        self.output += "A=M-1\n";
+        self.output += "M=D\n";
+        self.output += "@R15\n";
+        self.output += "A=M\n";
+        self.output += "0;JMP\n";
+    }
+
+    /// Shift and add: every set bit of y adds x shifted to that bit
+    fn write_multiply(&mut self) {
+        self.output += "($$MUL)\n";
+        self.math_operands();
+        self.output += "D=M\n";
+        self.output += "@MATH_A\n";
+        self.output += "M=D\n";
+        self.output += "@MATH_RESULT\n";
+        self.output += "M=0\n";
+        self.output += "@MATH_BIT\n";
+        self.output += "M=1\n";
+
+        self.output += "($$MUL_LOOP)\n";
+        // once the bit has been shifted out every bit of y has been looked at
+        self.output += "@MATH_BIT\n";
+        self.output += "D=M\n";
+        self.output += "@$$MUL_END\n";
+        self.output += "D;JEQ\n";
+        self.output += "@MATH_B\n";
+        self.output += "D=D&M\n";
+        self.output += "@$$MUL_SKIP\n";
+        self.output += "D;JEQ\n";
+        self.output += "@MATH_A\n";
+        self.output += "D=M\n";
+        self.output += "@MATH_RESULT\n";
+        self.output += "M=D+M\n";
+        self.output += "($$MUL_SKIP)\n";
+        self.output += "@MATH_A\n";
+        self.output += "D=M\n";
+        self.output += "M=D+M\n";
+        self.output += "@MATH_BIT\n";
+        self.output += "D=M\n";
+        self.output += "M=D+M\n";
+        self.output += "@$$MUL_LOOP\n";
+        self.output += "0;JMP\n";
+
+        self.output += "($$MUL_END)\n";
+        self.output += "@MATH_RESULT\n";
+        self.output += "D=M\n";
+        self.math_result();
+    }
+
+    /// Long division of |x| by |y| one bit at a time, with the signs put back afterwards.
+    /// `div` and `mod` share the loop and MATH_MOD says which result to hand back
+    fn write_divide(&mut self, divide: bool, modulo: bool) {
+        if divide {
+            self.output += "($$DIV)\n";
+            self.output += "@MATH_MOD\n";
+            self.output += "M=0\n";
+            if modulo {
+                self.output += "@$$DIVIDE\n";
+                self.output += "0;JMP\n";
+            }
+        }
+        if modulo {
+            self.output += "($$MOD)\n";
+            self.output += "@MATH_MOD\n";
+            self.output += "M=1\n";
+        }
+
+        self.output += "($$DIVIDE)\n";
+        self.math_operands();
+        self.output += "D=M\n";
+        self.output += "@MATH_A\n";
+        self.output += "M=D\n";
+        // MATH_X_SIGN gives the remainder its sign, MATH_SIGN the quotient
+        self.output += "@MATH_X_SIGN\n";
+        self.output += "M=0\n";
+        self.output += "@MATH_SIGN\n";
+        self.output += "M=0\n";
+        self.output += "@$$DIVIDE_X_POSITIVE\n";
+        self.output += "D;JGE\n";
+        self.output += "@MATH_A\n";
+        self.output += "M=-M\n";
+        self.output += "@MATH_X_SIGN\n";
+        self.output += "M=-1\n";
+        self.output += "@MATH_SIGN\n";
+        self.output += "M=-1\n";
+        self.output += "($$DIVIDE_X_POSITIVE)\n";
+        self.output += "@MATH_B\n";
+        self.output += "D=M\n";
+        self.output += "@$$DIVIDE_Y_POSITIVE\n";
+        self.output += "D;JGE\n";
+        self.output += "@MATH_B\n";
+        self.output += "M=-M\n";
+        self.output += "@MATH_SIGN\n";
+        self.output += "M=!M\n";
+        self.output += "($$DIVIDE_Y_POSITIVE)\n";
+
+        self.output += "@MATH_RESULT\n";
+        self.output += "M=0\n";
+        self.output += "@MATH_REMAINDER\n";
+        self.output += "M=0\n";
+        self.output += "@16\n";
+        self.output += "D=A\n";
+        self.output += "@MATH_BIT\n";
+        self.output += "M=D\n";
+
+        self.output += "($$DIVIDE_LOOP)\n";
+        // remainder = remainder * 2 + the top bit of a, then shift a and the quotient along
+        self.output += "@MATH_REMAINDER\n";
+        self.output += "D=M\n";
+        self.output += "M=D+M\n";
+        self.output += "@MATH_A\n";
+        self.output += "D=M\n";
+        self.output += "@$$DIVIDE_TOP_CLEAR\n";
+        self.output += "D;JGE\n";
+        self.output += "@MATH_REMAINDER\n";
+        self.output += "M=M+1\n";
+        self.output += "($$DIVIDE_TOP_CLEAR)\n";
+        self.output += "@MATH_A\n";
+        self.output += "D=M\n";
+        self.output += "M=D+M\n";
+        self.output += "@MATH_RESULT\n";
+        self.output += "D=M\n";
+        self.output += "M=D+M\n";
+
+        // remainder >= |y|, comparing them as unsigned numbers since either can be 32768 or more
+        self.output += "@MATH_REMAINDER\n";
+        self.output += "D=M\n";
+        self.output += "@$$DIVIDE_REMAINDER_HIGH\n";
+        self.output += "D;JLT\n";
+        self.output += "@MATH_B\n";
+        self.output += "D=M\n";
+        self.output += "@$$DIVIDE_NEXT\n";
+        self.output += "D;JLT\n";
+        self.output += "@$$DIVIDE_COMPARE\n";
+        self.output += "0;JMP\n";
+        self.output += "($$DIVIDE_REMAINDER_HIGH)\n";
+        self.output += "@MATH_B\n";
+        self.output += "D=M\n";
+        self.output += "@$$DIVIDE_SUBTRACT\n";
+        self.output += "D;JGE\n";
+        self.output += "($$DIVIDE_COMPARE)\n";
+        self.output += "@MATH_B\n";
+        self.output += "D=M\n";
+        self.output += "@MATH_REMAINDER\n";
+        self.output += "D=M-D\n";
+        self.output += "@$$DIVIDE_NEXT\n";
+        self.output += "D;JLT\n";
+        self.output += "($$DIVIDE_SUBTRACT)\n";
+        self.output += "@MATH_B\n";
+        self.output += "D=M\n";
+        self.output += "@MATH_REMAINDER\n";
+        self.output += "M=M-D\n";
+        self.output += "@MATH_RESULT\n";
+        self.output += "M=M+1\n";
+
+        self.output += "($$DIVIDE_NEXT)\n";
+        self.output += "@MATH_BIT\n";
+        self.output += "MD=M-1\n";
+        self.output += "@$$DIVIDE_LOOP\n";
+        self.output += "D;JGT\n";
+
+        // D = the sign the result should have, with the result itself in MATH_RESULT
+        self.output += "@MATH_MOD\n";
+        self.output += "D=M\n";
+        self.output += "@$$DIVIDE_REMAINDER\n";
+        self.output += "D;JNE\n";
+        self.output += "@MATH_SIGN\n";
+        self.output += "D=M\n";
+        self.output += "@$$DIVIDE_SIGN\n";
+        self.output += "0;JMP\n";
+        self.output += "($$DIVIDE_REMAINDER)\n";
+        self.output += "@MATH_REMAINDER\n";
+        self.output += "D=M\n";
+        self.output += "@MATH_RESULT\n";
+        self.output += "M=D\n";
+        self.output += "@MATH_X_SIGN\n";
+        self.output += "D=M\n";
+        self.output += "($$DIVIDE_SIGN)\n";
+        self.output += "@$$DIVIDE_DONE\n";
+        self.output += "D;JEQ\n";
+        self.output += "@MATH_RESULT\n";
+        self.output += "M=-M\n";
+        self.output += "($$DIVIDE_DONE)\n";
+        self.output += "@MATH_RESULT\n";
+        self.output += "D=M\n";
+        self.math_result();
+    }
+
+    /// Doubles x y times
+    fn write_shift_left(&mut self) {
+        self.output += "($$SHL)\n";
+        self.math_operands();
+        self.output += "D=M\n";
+        self.output += "@MATH_RESULT\n";
+        self.output += "M=D\n";
+        self.output += "($$SHL_LOOP)\n";
+        self.output += "@MATH_B\n";
+        self.output += "M=M-1\n";
+        self.output += "D=M+1\n";
+        self.output += "@$$SHL_END\n";
+        self.output += "D;JLE\n";
+        self.output += "@MATH_RESULT\n";
+        self.output += "D=M\n";
+        self.output += "M=D+M\n";
+        self.output += "@$$SHL_LOOP\n";
+        self.output += "0;JMP\n";
+        self.output += "($$SHL_END)\n";
+        self.output += "@MATH_RESULT\n";
+        self.output += "D=M\n";
+        self.math_result();
+    }
+
+    /// Copies each bit of x from position y and up into the result, starting from bit 0
+    fn write_shift_right(&mut self) {
+        self.output += "($$SHR)\n";
+        self.math_operands();
+        self.output += "D=M\n";
+        self.output += "@MATH_A\n";
+        self.output += "M=D\n";
+        self.output += "@MATH_RESULT\n";
+        self.output += "M=0\n";
+        self.output += "@MATH_TARGET\n";
+        self.output += "M=1\n";
+        self.output += "@MATH_BIT\n";
+        self.output += "M=1\n";
+
+        // MATH_BIT = 1 << y, which ends up as 0 for y >= 16
+        self.output += "($$SHR_START)\n";
+        self.output += "@MATH_B\n";
+        self.output += "M=M-1\n";
+        self.output += "D=M+1\n";
+        self.output += "@$$SHR_LOOP\n";
+        self.output += "D;JLE\n";
+        self.output += "@MATH_BIT\n";
+        self.output += "D=M\n";
+        self.output += "M=D+M\n";
+        self.output += "@$$SHR_START\n";
+        self.output += "0;JMP\n";
+
+        self.output += "($$SHR_LOOP)\n";
+        self.output += "@MATH_BIT\n";
+        self.output += "D=M\n";
+        self.output += "@$$SHR_END\n";
+        self.output += "D;JEQ\n";
+        self.output += "@MATH_A\n";
+        self.output += "D=D&M\n";
+        self.output += "@$$SHR_SKIP\n";
+        self.output += "D;JEQ\n";
+        self.output += "@MATH_TARGET\n";
+        self.output += "D=M\n";
+        self.output += "@MATH_RESULT\n";
+        self.output += "M=D|M\n";
+        self.output += "($$SHR_SKIP)\n";
+        self.output += "@MATH_BIT\n";
+        self.output += "D=M\n";
+        self.output += "M=D+M\n";
+        self.output += "@MATH_TARGET\n";
+        self.output += "D=M\n";
+        self.output += "M=D+M\n";
+        self.output += "@$$SHR_LOOP\n";
+        self.output += "0;JMP\n";
+
+        self.output += "($$SHR_END)\n";
+        self.output += "@MATH_RESULT\n";
+        self.output += "D=M\n";
+        self.math_result();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::cpu::Cpu;
+    use crate::vm::bootstrap::Bootstrap;
+    use crate::vm::parser::VmFile;
+    use crate::vm::top_of_stack::Strategy;
+
+    const VALUES: [i16; 12] = [0, 1, -1, 2, -2, 7, -7, 100, -300, 12345, 32767, -32768];
+    const SHIFTS: [i16; 8] = [-1, 0, 1, 2, 7, 15, 16, 17];
+
+    /// The vm commands that leave the value on the stack, -32768 included
+    fn push_value(value: i16) -> Vec<String> {
+        match value {
+            i16::MIN => vec![String::from("push constant 32767"), String::from("not")],
+            -32767..0 => vec![format!("push constant {}", -value), String::from("neg")],
+            _ => vec![format!("push constant {}", value)],
+        }
+    }
+
+    /// Translates `x y operator` for every pair and runs it on the Hack CPU, giving back
+    /// what each one left behind
+    fn run_math(operator: &str, pairs: &[(i16, i16)], strategy: Strategy) -> Vec<i16> {
+        // the results go to RAM[3000] onwards through `that`
+        let mut lines = vec![
+            String::from("push constant 3000"),
+            String::from("pop pointer 1"),
+        ];
+        for (index, (x, y)) in pairs.iter().enumerate() {
+            lines.extend(push_value(*x));
+            lines.extend(push_value(*y));
+            lines.push(operator.to_string());
+            lines.push(format!("pop that {}", index));
+        }
+        let file = VmFile::new(
+            String::from("Main"),
+            lines.iter().map(|line| line.parse().unwrap()).collect(),
+        );
+
+        let mut parser = Parser::new(5);
+        parser.strategy = strategy;
+        parser.bootstrap(&Bootstrap {
+            call_entry: false,
+            sp: Some(256),
+            ..Default::default()
+        });
+        parser.translate_file(&file, false);
+        parser.end_loop();
+        parser.write_math_routines();
+
+        let mut cpu = Cpu::from_asm(&parser.output);
+        cpu.run(10_000_000);
+        assert!(cpu.halted);
+        assert_eq!(
+            cpu.ram[0], 256,
+            "{} should leave the stack as it found it",
+            operator
+        );
+        cpu.ram[3000..3000 + pairs.len()].to_vec()
+    }
+
+    #[test]
+    fn test_math_routines() {
+        let pairs: Vec<(i16, i16)> = VALUES
+            .iter()
+            .flat_map(|x| VALUES.iter().map(move |y| (*x, *y)))
+            .collect();
+        let shift_pairs: Vec<(i16, i16)> = VALUES
+            .iter()
+            .flat_map(|x| SHIFTS.iter().map(move |y| (*x, *y)))
+            .collect();
+
+        for strategy in [Strategy::Stack, Strategy::Register] {
+            for operator in MATH_COMMANDS {
+                let pairs: Vec<(i16, i16)> = match operator {
+                    "shl" | "shr" => shift_pairs.clone(),
+                    // dividing by zero has no answer to check against
+                    "div" | "mod" => pairs.iter().copied().filter(|(_, y)| *y != 0).collect(),
+                    _ => pairs.clone(),
+                };
+                let results = run_math(operator, &pairs, strategy);
+                for ((x, y), result) in pairs.iter().zip(results) {
+                    assert_eq!(
+                        Some(result),
+                        evaluate_math(operator, *x, *y),
+                        "{} {} {} with {:?}",
+                        x,
+                        y,
+                        operator,
+                        strategy
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_math_routine_signs() {
+        let pairs = [(-7, 2), (7, -2), (-7, -2), (-32768, -1), (-32768, 3)];
+        assert_eq!(
+            run_math("div", &pairs, Strategy::Stack),
+            // towards zero, and -32768 / -1 wraps back around
+            [-3, -3, 3, -32768, -10922]
+        );
+        assert_eq!(
+            run_math("mod", &pairs, Strategy::Stack),
+            // the remainder takes the sign of x
+            [-1, 1, -1, 0, -2]
+        );
+        assert_eq!(
+            run_math("mul", &[(-3, 5), (-32768, -1), (181, 181)], Strategy::Stack),
+            [-15, -32768, 32761]
+        );
+        assert_eq!(
+            run_math("shr", &[(-32768, 15), (-1, 1)], Strategy::Stack),
+            [1, 32767]
+        );
+        assert_eq!(
+            run_math("shl", &[(1, 15), (-1, 16)], Strategy::Stack),
+            [-32768, 0]
+        );
+    }
+
+    #[test]
+    fn test_evaluate_math() {
+        assert_eq!(evaluate_math("mul", -7, 6), Some(-42));
+        assert_eq!(evaluate_math("div", -7, 2), Some(-3));
+        assert_eq!(evaluate_math("mod", -7, 2), Some(-1));
+        assert_eq!(evaluate_math("div", 1, 0), None);
+        assert_eq!(evaluate_math("shl", 3, 2), Some(12));
+        assert_eq!(evaluate_math("shr", -1, 12), Some(15));
+        assert_eq!(evaluate_math("shr", 5, 16), Some(0));
+        assert_eq!(evaluate_math("shl", 5, -1), Some(5));
+    }
+}
@@ -1,8 +1,22 @@
 pub mod arithmetic;
+pub mod bootstrap;
+pub mod c_backend;
+pub mod callgraph;
 pub mod commands;
+pub mod compact;
+pub mod emulator;
 pub mod function;
 pub mod goto;
 pub mod if_goto;
+pub mod inliner;
 pub mod label;
+pub mod math;
+pub mod optimizer;
 pub mod parser;
+pub mod profiler;
 pub mod run;
+pub mod source_map;
+pub mod statics;
+pub mod tail_call;
+pub mod top_of_stack;
+pub mod verify;
@@ -0,0 +1,491 @@
+use std::collections::BTreeSet;
+
+use crate::vm::callgraph::CallGraph;
+use crate::vm::commands::Command;
+use crate::vm::math::evaluate_math;
+use crate::vm::parser::VmFile;
+
+/// Runs the optimization passes over the program until none of them find anything left to do.
+/// With an entry point, functions that can never be called from it are dropped as well.
+/// Returns how many commands were removed
+pub fn optimize(files: &mut [VmFile], entry: Option<&str>) -> usize {
+    let before = command_count(files);
+
+    loop {
+        let mut changed = false;
+        for file in files.iter_mut() {
+            changed |= fold_constants(file);
+            changed |= remove_push_pop_pairs(file);
+            changed |= invert_conditions(file);
+            changed |= remove_dead_code(file);
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    if let Some(entry) = entry {
+        remove_unreachable_functions(files, entry);
+    }
+
+    before - command_count(files)
+}
+
+pub fn command_count(files: &[VmFile]) -> usize {
+    files.iter().map(|file| file.commands.len()).sum()
+}
+
+/// The commands a pass keeps or creates, along with the source line each one is credited to
+#[derive(Default)]
+struct Rewrite {
+    commands: Vec<Command>,
+    lines: Vec<usize>,
+}
+
+impl Rewrite {
+    fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    fn push(&mut self, command: Command, line: usize) {
+        self.commands.push(command);
+        self.lines.push(line);
+    }
+
+    fn extend(&mut self, commands: Vec<Command>, line: usize) {
+        for command in commands {
+            self.push(command, line);
+        }
+    }
+
+    fn pop(&mut self) {
+        self.commands.pop();
+        self.lines.pop();
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.commands.truncate(len);
+        self.lines.truncate(len);
+    }
+
+    fn finish(self, file: &mut VmFile) {
+        file.commands = self.commands;
+        file.lines = self.lines;
+    }
+}
+
+fn take_commands(file: &mut VmFile) -> impl Iterator<Item = (Command, usize)> + use<> {
+    let commands = std::mem::take(&mut file.commands);
+    let lines = std::mem::take(&mut file.lines);
+    commands.into_iter().zip(lines)
+}
+
+fn is_constant(segment: &str) -> bool {
+    segment == "constant"
+}
+
+fn is_operator(command: &Command, operators: &[&str]) -> bool {
+    match command {
+        Command::Arithmetic(operator) => operators.contains(&operator.as_str()),
+        _ => false,
+    }
+}
+
+/// Reads the constant pushed by the last commands, along with how many commands pushed it
+fn trailing_constant(commands: &[Command]) -> Option<(i16, usize)> {
+    match commands {
+        [
+            ..,
+            Command::Push(segment, value),
+            Command::Arithmetic(operator),
+        ] if is_constant(segment) && operator == "neg" => Some(((*value as i16).wrapping_neg(), 2)),
+        [
+            ..,
+            Command::Push(segment, value),
+            Command::Arithmetic(operator),
+        ] if is_constant(segment) && operator == "not" => Some((!(*value as i16), 2)),
+        [.., Command::Push(segment, value)] if is_constant(segment) => Some((*value as i16, 1)),
+        _ => None,
+    }
+}
+
+/// The fewest commands that push the value, since `push constant` only takes 0..=32767
+pub fn constant_commands(value: i16) -> Vec<Command> {
+    if value >= 0 {
+        vec![Command::push_constant(value as u16)]
+    } else if value == i16::MIN {
+        vec![
+            Command::push_constant(i16::MAX as u16),
+            Command::arithmetic("not"),
+        ]
+    } else {
+        vec![
+            Command::push_constant(value.unsigned_abs()),
+            Command::arithmetic("neg"),
+        ]
+    }
+}
+
+fn from_bool(value: bool) -> i16 {
+    if value { -1 } else { 0 }
+}
+
+fn fold_unary(operator: &str, y: i16) -> Option<i16> {
+    match operator {
+        "neg" => Some(y.wrapping_neg()),
+        "not" => Some(!y),
+        _ => None,
+    }
+}
+
+fn fold_binary(operator: &str, x: i16, y: i16) -> Option<i16> {
+    match operator {
+        "add" => Some(x.wrapping_add(y)),
+        "sub" => Some(x.wrapping_sub(y)),
+        "and" => Some(x & y),
+        "or" => Some(x | y),
+        "eq" => Some(from_bool(x == y)),
+        "gt" => Some(from_bool(x > y)),
+        "lt" => Some(from_bool(x < y)),
+        _ => evaluate_math(operator, x, y),
+    }
+}
+
+/// push constant a; push constant b; add => push constant (a + b)
+fn fold_constants(file: &mut VmFile) -> bool {
+    let mut changed = false;
+    let mut output = Rewrite::default();
+
+    for (command, line) in take_commands(file) {
+        if let Command::Arithmetic(operator) = &command
+            && let Some((y, y_len)) = trailing_constant(&output.commands)
+        {
+            if let Some(result) = fold_unary(operator, y) {
+                let folded = constant_commands(result);
+                // only worth it when the result is shorter than what it replaces
+                if folded.len() < y_len + 1 {
+                    output.truncate(output.len() - y_len);
+                    output.extend(folded, line);
+                    changed = true;
+                    continue;
+                }
+            } else if let Some((x, x_len)) =
+                trailing_constant(&output.commands[..output.len() - y_len])
+                && let Some(result) = fold_binary(operator, x, y)
+            {
+                output.truncate(output.len() - y_len - x_len);
+                output.extend(constant_commands(result), line);
+                changed = true;
+                continue;
+            }
+        }
+        output.push(command, line);
+    }
+
+    output.finish(file);
+    changed
+}
+
+/// push local 0; pop local 0 => nothing
+fn remove_push_pop_pairs(file: &mut VmFile) -> bool {
+    let mut changed = false;
+    let mut output = Rewrite::default();
+
+    for (command, line) in take_commands(file) {
+        if let Command::Pop(pop_segment, pop_index) = &command
+            && let Some(Command::Push(push_segment, push_index)) = output.commands.last()
+            && push_segment == pop_segment
+            && push_index == pop_index
+        {
+            output.pop();
+            changed = true;
+            continue;
+        }
+        output.push(command, line);
+    }
+
+    output.finish(file);
+    changed
+}
+
+/// Rewrites conditional jumps so they branch on the value that is already on the stack
+fn invert_conditions(file: &mut VmFile) -> bool {
+    let mut changed = false;
+    let mut output = Rewrite::default();
+
+    for (command, line) in take_commands(file) {
+        match (&command, output.commands.as_slice()) {
+            // not; not => nothing
+            (Command::Arithmetic(operator), [.., last])
+                if operator == "not" && is_operator(last, &["not"]) =>
+            {
+                output.pop();
+                changed = true;
+            }
+            // eq; not; if-goto L => eq; if-not-goto L
+            // only safe when the value being negated is a proper boolean
+            (Command::If(label), [.., comparison, last])
+                if is_operator(last, &["not"]) && is_operator(comparison, &["eq", "gt", "lt"]) =>
+            {
+                output.pop();
+                output.push(Command::IfNot(label.clone()), line);
+                changed = true;
+            }
+            (Command::IfNot(label), [.., comparison, last])
+                if is_operator(last, &["not"]) && is_operator(comparison, &["eq", "gt", "lt"]) =>
+            {
+                output.pop();
+                output.push(Command::If(label.clone()), line);
+                changed = true;
+            }
+            // if-goto T; goto F; label T => if-not-goto F; label T
+            (Command::Label(label), [.., Command::If(target), Command::Goto(other)])
+                if target == label =>
+            {
+                let other = other.clone();
+                output.truncate(output.len() - 2);
+                output.push(Command::IfNot(other), line);
+                output.push(command, line);
+                changed = true;
+            }
+            (Command::Label(label), [.., Command::IfNot(target), Command::Goto(other)])
+                if target == label =>
+            {
+                let other = other.clone();
+                output.truncate(output.len() - 2);
+                output.push(Command::If(other), line);
+                output.push(command, line);
+                changed = true;
+            }
+            // x == y is just x - y being zero
+            (Command::If(label), [.., last]) if is_operator(last, &["eq"]) => {
+                output.pop();
+                output.push(Command::arithmetic("sub"), line);
+                output.push(Command::IfNot(label.clone()), line);
+                changed = true;
+            }
+            (Command::IfNot(label), [.., last]) if is_operator(last, &["eq"]) => {
+                output.pop();
+                output.push(Command::arithmetic("sub"), line);
+                output.push(Command::If(label.clone()), line);
+                changed = true;
+            }
+            _ => output.push(command, line),
+        }
+    }
+
+    output.finish(file);
+    changed
+}
+
+/// Splits the commands at each `function` so labels can be looked at per function
+pub fn function_ranges(commands: &[Command]) -> Vec<(usize, usize)> {
+    let mut starts: Vec<usize> = commands
+        .iter()
+        .enumerate()
+        .filter(|(_, command)| matches!(command, Command::Function(..)))
+        .map(|(i, _)| i)
+        .collect();
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+
+    let mut ranges = vec![];
+    for (i, start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).copied().unwrap_or(commands.len());
+        ranges.push((*start, end));
+    }
+    ranges
+}
+
+/// Removes commands that can never run, jumps to the very next command and labels nothing jumps to
+fn remove_dead_code(file: &mut VmFile) -> bool {
+    let mut output = Rewrite::default();
+
+    for (start, end) in function_ranges(&file.commands) {
+        let body = &file.commands[start..end];
+        let targets: BTreeSet<&String> = body
+            .iter()
+            .filter_map(|command| match command {
+                Command::Goto(label) | Command::If(label) | Command::IfNot(label) => Some(label),
+                _ => None,
+            })
+            .collect();
+
+        let mut reachable = true;
+        for (command, line) in body.iter().zip(&file.lines[start..end]) {
+            match command {
+                Command::Label(label) => {
+                    if !targets.contains(label) {
+                        continue;
+                    }
+                    reachable = true;
+                }
+                Command::Function(..) => reachable = true,
+                _ => {}
+            }
+            if !reachable {
+                continue;
+            }
+
+            // goto L; label L => label L
+            if let Command::Label(label) = command
+                && let Some(Command::Goto(target)) = output.commands.last()
+                && target == label
+            {
+                output.pop();
+            }
+
+            output.push(command.clone(), *line);
+            if command.is_unconditional_jump() {
+                reachable = false;
+            }
+        }
+    }
+
+    let changed = output.len() != file.commands.len();
+    output.finish(file);
+    changed
+}
+
+/// Drops every function that can't be reached by following calls from the entry point
+fn remove_unreachable_functions(files: &mut [VmFile], entry: &str) {
+    let reachable = CallGraph::new(files).reachable(entry);
+
+    for file in files.iter_mut() {
+        let mut output = Rewrite::default();
+        for (start, end) in function_ranges(&file.commands) {
+            if let Some(Command::Function(name, _)) = file.commands.get(start)
+                && !reachable.contains(name)
+            {
+                continue;
+            }
+            for i in start..end {
+                output.push(file.commands[i].clone(), file.lines[i]);
+            }
+        }
+        output.finish(file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn commands(lines: &[&str]) -> Vec<Command> {
+        lines
+            .iter()
+            .map(|line| Command::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn file(lines: &[&str]) -> VmFile {
+        VmFile::new(String::from("Main"), commands(lines))
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        let mut program = file(&["push constant 2", "push constant 3", "add"]);
+        fold_constants(&mut program);
+        assert_eq!(program.commands, commands(&["push constant 5"]));
+
+        let mut program = file(&["push constant 2", "push constant 3", "sub"]);
+        fold_constants(&mut program);
+        assert_eq!(program.commands, commands(&["push constant 1", "neg"]));
+
+        // true stays the way the compiler writes it
+        let mut program = file(&["push constant 0", "not"]);
+        assert!(!fold_constants(&mut program));
+
+        let mut program = file(&["push constant 0", "not", "push constant 0", "and"]);
+        fold_constants(&mut program);
+        assert_eq!(program.commands, commands(&["push constant 0"]));
+    }
+
+    #[test]
+    fn test_remove_push_pop_pairs() {
+        let mut program = file(&["push local 0", "pop local 0", "push local 1", "pop local 0"]);
+        remove_push_pop_pairs(&mut program);
+        assert_eq!(program.commands, commands(&["push local 1", "pop local 0"]));
+    }
+
+    #[test]
+    fn test_invert_conditions() {
+        let mut program = file(&["lt", "not", "if-goto WHILE_END0"]);
+        invert_conditions(&mut program);
+        assert_eq!(
+            program.commands,
+            commands(&["lt", "if-not-goto WHILE_END0"])
+        );
+
+        // a bitwise not of an arbitrary value isn't the same as testing for false
+        let mut program = file(&["push local 0", "not", "if-goto END"]);
+        assert!(!invert_conditions(&mut program));
+
+        let mut program = file(&["if-goto IF_TRUE0", "goto IF_FALSE0", "label IF_TRUE0"]);
+        invert_conditions(&mut program);
+        assert_eq!(
+            program.commands,
+            commands(&["if-not-goto IF_FALSE0", "label IF_TRUE0"])
+        );
+    }
+
+    #[test]
+    fn test_remove_dead_code() {
+        let mut program = file(&[
+            "function Main.main 0",
+            "goto END",
+            "push constant 1",
+            "label UNUSED",
+            "pop temp 0",
+            "label END",
+            "push constant 0",
+            "return",
+            "push constant 2",
+            "function Main.other 0",
+            "push constant 0",
+            "return",
+        ]);
+        remove_dead_code(&mut program);
+        assert_eq!(
+            program.commands,
+            commands(&[
+                "function Main.main 0",
+                "label END",
+                "push constant 0",
+                "return",
+                "function Main.other 0",
+                "push constant 0",
+                "return",
+            ])
+        );
+    }
+
+    #[test]
+    fn test_remove_unreachable_functions() {
+        let mut files = vec![VmFile::new(
+            String::from("Main"),
+            commands(&[
+                "function Main.main 0",
+                "call Main.used 0",
+                "return",
+                "function Main.used 0",
+                "push constant 0",
+                "return",
+                "function Main.unused 0",
+                "push constant 0",
+                "return",
+            ]),
+        )];
+        remove_unreachable_functions(&mut files, "Main.main");
+        assert_eq!(files[0].commands.len(), 6);
+        assert_eq!(files[0].lines, vec![1, 2, 3, 4, 5, 6]);
+        assert!(
+            !files[0]
+                .commands
+                .contains(&Command::Function(String::from("Main.unused"), 0))
+        );
+    }
+}
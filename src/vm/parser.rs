@@ -1,17 +1,61 @@
+use std::collections::BTreeSet;
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
 use std::str::FromStr;
 
-use crate::vm::commands::CommandType;
+use crate::vm::commands::Command;
+use crate::vm::compact::CodeGen;
+use crate::vm::source_map::SourceEntry;
+use crate::vm::top_of_stack::Strategy;
+
+/// The commands of one .vm file, ready to be optimized or translated
+#[derive(Debug, Clone)]
+pub struct VmFile {
+    pub name: String,
+    pub commands: Vec<Command>,
+    /// The line in the .vm file each command came from
+    pub lines: Vec<usize>,
+}
+
+impl VmFile {
+    /// Treats the commands as the lines of the file, one after another
+    pub fn new(name: String, commands: Vec<Command>) -> Self {
+        let lines = (1..=commands.len()).collect();
+        VmFile {
+            name,
+            commands,
+            lines,
+        }
+    }
+}
 
 pub struct Parser {
     pub output: String,
     pub general_return_number: u64,
     pub caller_return_number: u64,
-    temp_base: u32,
+    pub temp_base: u32,
     pub file_name: String,
     pub function_name: String,
+    pub code_gen: CodeGen,
+    pub strategy: Strategy,
+    /// Whether D holds the top of the stack instead of RAM[SP-1]
+    pub top_in_d: bool,
+    /// A constant pushed on top of the value in D that hasn't been written anywhere yet
+    pub pending_constant: Option<u16>,
+    /// The extended math commands used so far, whose routines get written at the end
+    pub math_routines: BTreeSet<String>,
+    /// Turn `call f n; return` into a jump that reuses the current frame
+    pub tail_calls: bool,
+    /// Set while translating a call that is followed by a return
+    pub tail_call: bool,
+    /// Write each vm command as a comment above its assembly
+    pub annotate: bool,
+    /// Collects where each command's assembly starts and ends when set
+    pub source_map: Option<Vec<SourceEntry>>,
+    /// How much of the output has been checked for newlines, and how many were found
+    pub counted_bytes: usize,
+    pub counted_lines: usize,
 }
 
 impl Parser {
@@ -23,43 +67,43 @@ impl Parser {
             temp_base,
             file_name: String::new(),
             function_name: String::new(),
+            code_gen: CodeGen::Inline,
+            strategy: Strategy::Stack,
+            top_in_d: false,
+            pending_constant: None,
+            math_routines: BTreeSet::new(),
+            tail_calls: false,
+            tail_call: false,
+            annotate: false,
+            source_map: None,
+            counted_bytes: 0,
+            counted_lines: 0,
         }
     }
 
-    pub fn bootstrap(&mut self) {
-        self.output = String::from("@256\nD=A\n@SP\nM=D\n");
-        // self.output += "@300\nD=A\n@LCL\nM=D\n";
-        // self.output += "@400\nD=A\n@ARG\nM=D\n";
-        // self.output += "@3000\nD=A\n@THIS\nM=D\n";
-        // self.output += "@3010\nD=A\n@THAT\nM=D\n";
-        self.handle_call(vec!["call", "Sys.init", "0"]);
-
-        // self.handle_call(vec!["call", "Main.main", "0"]);
-        // self.output += "(END)\n";
-        // self.output += "@END\n";
-        // self.output += "0;JMP\n";
-    }
-
+    #[cfg(test)]
     fn parse_line(&mut self, line: String) {
-        let trimmed_line = line.trim();
+        if let Some(command) = parse_line(&line) {
+            self.translate_command(&command);
+        }
+    }
 
-        if trimmed_line.starts_with("//") | trimmed_line.is_empty() {
+    pub fn translate_command(&mut self, command: &Command) {
+        if self.strategy == Strategy::Register && self.translate_cached(command) {
             return;
         }
 
-        let tokens: Vec<&str> = trimmed_line.split(" ").collect();
-        let command = CommandType::from_str(tokens[0]).expect("Line isn't empty now");
-
         match command {
-            CommandType::Push => self.handle_push(tokens),
-            CommandType::Pop => self.handle_pop(tokens),
-            CommandType::Arithmetic => self.handle_arithmetic(tokens),
-            CommandType::Label => self.handle_label(tokens),
-            CommandType::If => self.handle_if_goto(tokens),
-            CommandType::Goto => self.handle_goto(tokens),
-            CommandType::Function => self.handle_function(tokens),
-            CommandType::Return => self.handle_return(),
-            CommandType::Call => self.handle_call(tokens),
+            Command::Push(segment, index) => self.handle_push(segment, *index),
+            Command::Pop(segment, index) => self.handle_pop(segment, *index),
+            Command::Arithmetic(operator) => self.handle_arithmetic(operator),
+            Command::Label(label) => self.handle_label(label),
+            Command::If(label) => self.handle_if_goto(label),
+            Command::IfNot(label) => self.handle_if_not_goto(label),
+            Command::Goto(label) => self.handle_goto(label),
+            Command::Function(name, locals) => self.handle_function(name, *locals),
+            Command::Return => self.handle_return(),
+            Command::Call(name, args) => self.handle_call(name, *args),
         }
     }
 
@@ -72,7 +116,7 @@ impl Parser {
         self.output += "D=M\n"; // now stored in D
     }
 
-    fn handle_pop(&mut self, tokens: Vec<&str>) {
+    fn handle_pop(&mut self, segment: &str, value: u16) {
         // LCL = RAM[SP--]
         // @SP
         // A=M
@@ -84,13 +128,11 @@ impl Parser {
         // M=D
         // @LCL
         // M=M+1
-        let segment = tokens[1];
-        let value = tokens[2];
 
         if segment == "pointer" {
             self.pop_stack();
 
-            if value == "0" {
+            if value == 0 {
                 self.output += "@THIS\n";
             } else {
                 self.output += "@THAT\n";
@@ -156,7 +198,7 @@ impl Parser {
         } else if segment == "temp" {
             self.pop_stack();
 
-            self.output += &format!("@{}\n", self.temp_base + value.parse::<u32>().unwrap());
+            self.output += &format!("@{}\n", self.temp_base + value as u32);
             self.output += "M=D\n";
         } else {
             panic!("Found an unknown segment: {}", segment);
@@ -172,7 +214,7 @@ impl Parser {
         self.output += "M=M+1\n";
     }
 
-    fn handle_push(&mut self, tokens: Vec<&str>) {
+    fn handle_push(&mut self, segment: &str, value: u16) {
         // RAM[SP++] = D
         // @SP
         // A=M
@@ -188,16 +230,19 @@ impl Parser {
         // M=D
         // @SP
         // M=M+1
-        let segment = tokens[1];
-        let value = tokens[2];
+        self.load_segment(segment, value);
+        self.push_d();
+    }
 
+    /// Read the value at segment[value] into D
+    pub fn load_segment(&mut self, segment: &str, value: u16) {
         match segment {
             "constant" => {
                 self.output += &format!("@{}\n", value);
                 self.output += "D=A\n";
             }
             "pointer" => {
-                if value == "0" {
+                if value == 0 {
                     self.output += "@THIS\n";
                 } else {
                     self.output += "@THAT\n";
@@ -241,7 +286,7 @@ impl Parser {
                 self.output += "D=M\n";
             }
             "temp" => {
-                let index = self.temp_base + value.parse::<u32>().unwrap();
+                let index = self.temp_base + value as u32;
                 self.output += &format!("@{}\n", index);
                 self.output += "D=M\n"; // now stored in D
             }
@@ -249,36 +294,102 @@ impl Parser {
                 panic!("Found an unknown segment: {}", segment);
             }
         }
-        self.push_d();
     }
 
-    pub fn parse_file<P>(&mut self, file: P, debug: bool)
-    where
-        P: AsRef<Path> + std::fmt::Debug,
-    {
+    pub fn translate_file(&mut self, file: &VmFile, debug: bool) {
         if debug {
-            println!("Parsing {}", file.as_ref().display());
+            println!("Translating {}", file.name);
         }
-        let file_name = file
-            .as_ref()
-            .file_stem()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_owned();
-        self.file_name = file_name;
-
-        let input_contents =
-            File::open(file).expect("At this point we should know we have a .vm file");
-
-        for line in io::BufReader::new(input_contents)
-            .lines()
-            .map_while(Result::ok)
-        {
+        self.file_name = file.name.clone();
+
+        let mut commands = file.commands.iter().zip(&file.lines).peekable();
+        while let Some((command, line)) = commands.next() {
             if debug {
-                println!("{}", line);
+                println!("{}", command);
             }
-            self.parse_line(line);
+            // the return is taken care of by the tail call
+            self.tail_call = self.tail_calls
+                && matches!(command, Command::Call(..))
+                && commands
+                    .next_if(|(next, _)| **next == Command::Return)
+                    .is_some();
+            self.translate_mapped(command, *line);
+            self.tail_call = false;
         }
+        // leave the stack complete for whatever comes next
+        self.spill();
+    }
+}
+
+/// Turns a line of a .vm file into a command, skipping blanks and comments
+pub fn parse_line(line: &str) -> Option<Command> {
+    let code = match line.split_once("//") {
+        Some((code, _)) => code,
+        None => line,
+    }
+    .trim();
+
+    if code.is_empty() {
+        return None;
+    }
+
+    match Command::from_str(code) {
+        Ok(command) => Some(command),
+        Err(_) => panic!("Could not parse the vm command '{}'", code),
+    }
+}
+
+pub fn parse_file<P>(file: P) -> VmFile
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let file_name = file
+        .as_ref()
+        .file_stem()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    let input_contents = File::open(file).expect("At this point we should know we have a .vm file");
+
+    let mut vm_file = VmFile::new(file_name, vec![]);
+    for (number, line) in io::BufReader::new(input_contents)
+        .lines()
+        .map_while(Result::ok)
+        .enumerate()
+    {
+        if let Some(command) = parse_line(&line) {
+            vm_file.commands.push(command);
+            vm_file.lines.push(number + 1);
+        }
+    }
+
+    vm_file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labels_scoped_to_function() {
+        let mut parser = Parser::new(5);
+        for line in [
+            "function Foo.a 0",
+            "label WHILE_EXP0",
+            "goto WHILE_EXP0",
+            "function Foo.b 0",
+            "label WHILE_EXP0",
+            "push constant 0",
+            "if-goto WHILE_EXP0",
+        ] {
+            parser.parse_line(line.to_string());
+        }
+
+        assert!(parser.output.contains("(Foo.a$WHILE_EXP0)\n"));
+        assert!(parser.output.contains("@Foo.a$WHILE_EXP0\n0;JMP\n"));
+        assert!(parser.output.contains("(Foo.b$WHILE_EXP0)\n"));
+        assert!(parser.output.contains("@Foo.b$WHILE_EXP0\nD;JNE\n"));
     }
 }
@@ -0,0 +1,278 @@
+use std::collections::BTreeMap;
+
+use crate::vm::commands::Command;
+use crate::vm::emulator::{Emulator, EmulatorError};
+
+/// What happened inside one function while the program ran
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FunctionProfile {
+    pub calls: u64,
+    /// Commands run in the function itself
+    pub exclusive: u64,
+    /// Commands run in the function and everything it called
+    pub inclusive: u64,
+    /// Most stack words in use, counted from the start of the program, while it was running
+    pub max_stack: i32,
+}
+
+/// A function that is currently running
+#[derive(Debug)]
+struct Frame {
+    name: String,
+    started: u64,
+    max_sp: i16,
+}
+
+/// Follows the calls and returns of an emulator run to build up per function counts
+#[derive(Debug, Default)]
+pub struct Profiler {
+    pub functions: BTreeMap<String, FunctionProfile>,
+    /// Commands run under each chain of calls, keyed like "Sys.init;Main.main"
+    pub folded: BTreeMap<String, u64>,
+    frames: Vec<Frame>,
+    base_sp: i16,
+}
+
+impl Profiler {
+    /// Starts profiling from wherever the emulator is. `entry` is the function the
+    /// bootstrap has just called, if it did
+    pub fn new(emulator: &Emulator, entry: Option<&str>) -> Self {
+        let mut profiler = Profiler {
+            base_sp: emulator.ram[0],
+            ..Default::default()
+        };
+        if let Some(entry) = entry {
+            // the bootstrap has already pushed the entry function's frame
+            profiler.base_sp = emulator.ram[0].wrapping_sub(5);
+            profiler.enter(entry, emulator);
+        }
+        profiler
+    }
+
+    /// Runs the emulator until it stops, keeping count along the way. If the program
+    /// fails, the counts still cover everything up to and including the failing command
+    pub fn run(&mut self, emulator: &mut Emulator, max_steps: u64) -> Result<(), EmulatorError> {
+        while !emulator.halted && emulator.steps < max_steps {
+            let command = emulator.commands.get(emulator.pc).cloned();
+            if let Err(error) = emulator.step() {
+                // the failing command never got to call or return
+                self.record(None, emulator);
+                self.finish(emulator);
+                return Err(error);
+            }
+            self.record(command.as_ref(), emulator);
+        }
+        self.finish(emulator);
+        Ok(())
+    }
+
+    fn record(&mut self, command: Option<&Command>, emulator: &Emulator) {
+        let sp = emulator.ram[0];
+        if let Some(frame) = self.frames.last_mut() {
+            frame.max_sp = frame.max_sp.max(sp);
+        }
+        // the command counts towards whoever was running it
+        let path = self.path();
+        if let Some(frame) = self.frames.last() {
+            self.functions
+                .entry(frame.name.clone())
+                .or_default()
+                .exclusive += 1;
+        }
+        *self.folded.entry(path).or_default() += 1;
+
+        match command {
+            Some(Command::Call(name, _)) => self.enter(name, emulator),
+            Some(Command::Return) => self.leave(emulator.steps),
+            _ => {}
+        }
+    }
+
+    fn enter(&mut self, name: &str, emulator: &Emulator) {
+        self.functions.entry(name.to_string()).or_default().calls += 1;
+        self.frames.push(Frame {
+            name: name.to_string(),
+            started: emulator.steps,
+            max_sp: emulator.ram[0],
+        });
+    }
+
+    fn leave(&mut self, steps: u64) {
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+        // a recursive call's time is already part of the outermost call's
+        let recursive = self.frames.iter().any(|outer| outer.name == frame.name);
+        let profile = self.functions.entry(frame.name.clone()).or_default();
+        if !recursive {
+            profile.inclusive += steps - frame.started;
+        }
+        profile.max_stack = profile
+            .max_stack
+            .max(frame.max_sp as i32 - self.base_sp as i32);
+
+        if let Some(caller) = self.frames.last_mut() {
+            caller.max_sp = caller.max_sp.max(frame.max_sp);
+        }
+    }
+
+    /// Counts the functions that were still running when the program stopped
+    fn finish(&mut self, emulator: &Emulator) {
+        while !self.frames.is_empty() {
+            self.leave(emulator.steps);
+        }
+    }
+
+    fn path(&self) -> String {
+        let names: Vec<&str> = self
+            .frames
+            .iter()
+            .map(|frame| frame.name.as_str())
+            .collect();
+        if names.is_empty() {
+            String::from("(top level)")
+        } else {
+            names.join(";")
+        }
+    }
+
+    /// One line per chain of calls, in the format flamegraph tools read
+    pub fn folded_stacks(&self) -> String {
+        self.folded
+            .iter()
+            .map(|(path, count)| format!("{} {}\n", path, count))
+            .collect()
+    }
+
+    /// Functions with the most commands run in them first
+    pub fn report(&self) -> String {
+        let total: u64 = self
+            .functions
+            .values()
+            .map(|profile| profile.exclusive)
+            .sum();
+        let mut functions: Vec<(&String, &FunctionProfile)> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(b.0)));
+
+        let mut output = format!(
+            "{:<30} {:>8} {:>12} {:>7} {:>12} {:>6}\n",
+            "function", "calls", "exclusive", "%", "inclusive", "stack"
+        );
+        for (name, profile) in functions {
+            let percent = match total {
+                0 => 0.0,
+                _ => 100.0 * profile.exclusive as f64 / total as f64,
+            };
+            output += &format!(
+                "{:<30} {:>8} {:>12} {:>6.1}% {:>12} {:>6}\n",
+                name,
+                profile.calls,
+                profile.exclusive,
+                percent,
+                profile.inclusive,
+                profile.max_stack
+            );
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::bootstrap::Bootstrap;
+    use crate::vm::parser::VmFile;
+
+    #[test]
+    fn test_profiler() {
+        let program = vec![VmFile::new(
+            String::from("Main"),
+            [
+                "function Sys.init 0",
+                "push constant 3",
+                "call Main.down 1",
+                "pop temp 0",
+                "label END",
+                "goto END",
+                "function Main.down 0",
+                "push argument 0",
+                "if-goto RECURSE",
+                "push constant 0",
+                "return",
+                "label RECURSE",
+                "push argument 0",
+                "push constant 1",
+                "sub",
+                "call Main.down 1",
+                "return",
+            ]
+            .iter()
+            .map(|line| line.parse().unwrap())
+            .collect(),
+        )];
+
+        let mut emulator = Emulator::new(&program);
+        emulator.bootstrap(&Bootstrap::default()).unwrap();
+        let mut profiler = Profiler::new(&emulator, Some("Sys.init"));
+        profiler.run(&mut emulator, 1000).unwrap();
+
+        let down = &profiler.functions["Main.down"];
+        assert_eq!(down.calls, 4);
+        // three calls that recurse (9 commands each) and one that doesn't (5)
+        assert_eq!(down.exclusive, 3 * 9 + 5);
+        assert_eq!(down.inclusive, down.exclusive);
+        // the entry's saved frame, then an argument and 5 saved words
+        // for each call and one value on top
+        assert_eq!(down.max_stack, 5 + 4 * 6 + 1);
+
+        let init = &profiler.functions["Sys.init"];
+        assert_eq!(init.calls, 1);
+        assert_eq!(init.inclusive, emulator.steps);
+        assert_eq!(
+            profiler.folded["Sys.init;Main.down;Main.down;Main.down;Main.down"],
+            5
+        );
+    }
+
+    #[test]
+    fn test_profiler_on_error() {
+        let program = vec![VmFile::new(
+            String::from("Main"),
+            [
+                "function Sys.init 0",
+                "call Main.outer 0",
+                "pop temp 0",
+                "label END",
+                "goto END",
+                "function Main.outer 0",
+                "push constant 7",
+                "call Main.divide 1",
+                "return",
+                "function Main.divide 0",
+                "push argument 0",
+                "push constant 0",
+                "div",
+                "return",
+            ]
+            .iter()
+            .map(|line| line.parse().unwrap())
+            .collect(),
+        )];
+
+        let mut emulator = Emulator::new(&program);
+        emulator.bootstrap(&Bootstrap::default()).unwrap();
+        let mut profiler = Profiler::new(&emulator, Some("Sys.init"));
+        let error = profiler.run(&mut emulator, 1000).unwrap_err();
+        assert_eq!(error.message, "division by zero");
+
+        // every frame was still open when `div` failed, and the `div` itself counts
+        let divide = &profiler.functions["Main.divide"];
+        assert_eq!(divide.exclusive, 4);
+        assert_eq!(divide.inclusive, 4);
+        // the saved frames of all three calls, the argument and both of div's operands
+        assert_eq!(divide.max_stack, 3 * 5 + 1 + 2);
+        assert_eq!(profiler.functions["Main.outer"].inclusive, 3 + 4);
+        assert_eq!(profiler.functions["Sys.init"].inclusive, emulator.steps);
+        assert_eq!(profiler.folded["Sys.init;Main.outer;Main.divide"], 4);
+    }
+}
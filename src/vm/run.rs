@@ -1,35 +1,194 @@
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 
-use crate::vm::parser::Parser;
+use clap::ValueEnum;
 
-pub fn run_vm<P>(path: P, debug: bool)
+use crate::vm::bootstrap::Bootstrap;
+use crate::vm::commands::Command;
+use crate::vm::compact::{CodeGen, instruction_count};
+use crate::vm::inliner::inline;
+use crate::vm::optimizer::{command_count, optimize};
+use crate::vm::parser::{Parser, VmFile, parse_file};
+use crate::vm::statics::StaticReport;
+use crate::vm::top_of_stack::Strategy;
+use crate::vm::verify::verify;
+
+/// Number of instructions that fit in the Hack ROM
+const ROM_SIZE: usize = 32768;
+
+/// Everything that changes how the .vm files get translated
+#[derive(Debug, Clone)]
+pub struct VmOptions {
+    pub bootstrap: Bootstrap,
+    pub code_gen: CodeGen,
+    pub strategy: Strategy,
+    /// Report the output size of every stack strategy
+    pub compare_strategies: bool,
+    pub optimize: bool,
+    /// Reuse the current frame for calls that are immediately returned
+    pub tail_calls: bool,
+    /// Copy functions with at most this many commands into their callers
+    pub inline: Option<usize>,
+    /// File listing the .vm files to translate, in order
+    pub manifest: Option<PathBuf>,
+    /// Write each vm command as a comment above its assembly
+    pub annotate: bool,
+    /// Write a .asm.json file mapping the assembly back to the vm lines
+    pub source_map: bool,
+    /// Print how much of RAM 16-255 each file's statics take up
+    pub statics: bool,
+    /// Check every function's use of the stack before translating
+    pub verify: bool,
+}
+
+pub fn run_vm<P>(path: P, options: VmOptions, debug: bool)
 where
     P: AsRef<Path> + std::fmt::Debug,
 {
     println!("Running the vm on '{}'", path.as_ref().display());
 
-    let files = valid_files(&path);
+    let files = match &options.manifest {
+        Some(manifest) => manifest_files(manifest),
+        None => valid_files(&path),
+    };
     if files.is_none() {
         println!("Could not find any valid '.vm' files to work on.");
         return;
     }
 
-    let mut parser = Parser::new(5);
-    parser.bootstrap();
-    for file in files.expect("Should have something after .is_none() check") {
-        parser.parse_file(file, debug);
+    let mut program: Vec<VmFile> = files
+        .expect("Should have something after .is_none() check")
+        .iter()
+        .map(parse_file)
+        .collect();
+
+    let bootstrap = &options.bootstrap;
+    if bootstrap.call_entry && !defines_function(&program, &bootstrap.entry) {
+        println!(
+            "Could not find a '{}' function for the bootstrap code to call.",
+            bootstrap.entry
+        );
+        return;
+    }
+
+    if options.verify {
+        let errors = verify(&program);
+        if !errors.is_empty() {
+            for error in &errors {
+                println!("{}", error);
+            }
+            println!("Found {} stack errors, not translating", errors.len());
+            return;
+        }
+    }
+
+    if let Some(threshold) = options.inline {
+        let inlined = inline(&mut program, threshold);
+        println!("Inlined {} calls", inlined);
+    }
+
+    if options.optimize {
+        let before = command_count(&program);
+        let entry = bootstrap.call_entry.then_some(bootstrap.entry.as_str());
+        let removed = optimize(&mut program, entry);
+        println!("Optimizer removed {} of {} vm commands", removed, before);
+    }
+
+    let mut parser = translate(&program, &options, debug);
+    let output = parser.output.clone();
+
+    let instructions = instruction_count(&output);
+    if options.code_gen == CodeGen::Compact {
+        let inline_options = VmOptions {
+            code_gen: CodeGen::Inline,
+            ..options.clone()
+        };
+        let inline_instructions =
+            instruction_count(&translate(&program, &inline_options, false).output);
+        println!(
+            "Compact output is {} instructions, inline would be {} ({} saved)",
+            instructions,
+            inline_instructions,
+            inline_instructions as i64 - instructions as i64
+        );
+    }
+    if options.compare_strategies {
+        for strategy in Strategy::value_variants() {
+            let strategy_options = VmOptions {
+                strategy: *strategy,
+                ..options.clone()
+            };
+            let strategy_instructions =
+                instruction_count(&translate(&program, &strategy_options, false).output);
+            println!(
+                "{:?} strategy: {} instructions",
+                strategy, strategy_instructions
+            );
+        }
+    }
+    let report = StaticReport::new(&program, &output);
+    if !report.fits() {
+        println!("Error: the program's variables don't fit below the stack at 256");
+        report.print();
+        return;
+    }
+    if options.statics {
+        report.print();
+    }
+    if instructions > ROM_SIZE {
+        println!(
+            "Warning: output is {} instructions, which doesn't fit in the {} word ROM",
+            instructions, ROM_SIZE
+        );
     }
 
     let output_path = create_output_path(&path);
-    let mut output_file = File::create(output_path).unwrap();
-    output_file.write_all(parser.output.as_bytes()).unwrap();
+    let mut output_file = File::create(&output_path).unwrap();
+    output_file.write_all(output.as_bytes()).unwrap();
+
+    if let Some(source_map) = parser.source_map.take() {
+        let map_path = source_map_path(&output_path);
+        let json = serde_json::to_string_pretty(&source_map).unwrap();
+        fs::write(&map_path, json).unwrap();
+        println!("Saving source map to {:?}", map_path);
+    }
+}
+
+/// Where the source map for a generated file goes, e.g. Prog.asm -> Prog.asm.json
+pub fn source_map_path(output_path: &Path) -> PathBuf {
+    let mut map_path = output_path.as_os_str().to_owned();
+    map_path.push(".json");
+    PathBuf::from(map_path)
+}
+
+/// Translates the parsed .vm files into a single assembly program
+fn translate(program: &[VmFile], options: &VmOptions, debug: bool) -> Parser {
+    let bootstrap = &options.bootstrap;
+    let mut parser = Parser::new(5);
+    parser.code_gen = options.code_gen;
+    parser.strategy = options.strategy;
+    parser.tail_calls = options.tail_calls;
+    parser.annotate = options.annotate;
+    parser.source_map = options.source_map.then(Vec::new);
+    parser.bootstrap(bootstrap);
+    for file in program {
+        parser.translate_file(file, debug);
+    }
+    if !bootstrap.call_entry && bootstrap.end_loop {
+        parser.end_loop();
+    }
+    if options.code_gen == CodeGen::Compact {
+        parser.write_shared_routines();
+    }
+    parser.write_math_routines();
+
+    parser
 }
 
 /// Returns paths to valid .jack files
-fn valid_files<P>(file: &P) -> Option<Vec<PathBuf>>
+pub fn valid_files<P>(file: &P) -> Option<Vec<PathBuf>>
 where
     P: AsRef<Path> + std::fmt::Debug + ?Sized,
 {
@@ -48,16 +207,18 @@ where
         {
             let entry_path = entry.path();
             let extension = entry_path.extension();
-            if let Some(extension) = extension {
-                if extension == "vm" {
-                    files.push(entry.path().to_path_buf())
-                }
+            if let Some(extension) = extension
+                && extension == "vm"
+            {
+                files.push(entry.path().to_path_buf())
             }
         }
 
         if files.is_empty() {
             return None;
         }
+        // read_dir order depends on the filesystem, so sort for reproducible output
+        files.sort();
         return Some(files);
     }
 
@@ -82,14 +243,52 @@ where
     None
 }
 
-fn create_output_path<P>(file: P) -> PathBuf
+/// Reads the .vm files listed in a manifest, one per line and relative to the manifest.
+/// Blank lines and lines starting with '#' are skipped
+fn manifest_files(manifest: &Path) -> Option<Vec<PathBuf>> {
+    let Ok(contents) = fs::read_to_string(manifest) else {
+        println!("Could not read the manifest '{}'", manifest.display());
+        return None;
+    };
+    let base = manifest.parent().unwrap_or(Path::new("."));
+
+    let mut files = vec![];
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("#") {
+            continue;
+        }
+
+        let file = base.join(line);
+        if !file.is_file() || file.extension().is_none_or(|extension| extension != "vm") {
+            println!("Manifest entry '{}' isn't an existing .vm file", line);
+            return None;
+        }
+        files.push(file);
+    }
+
+    if files.is_empty() {
+        return None;
+    }
+    Some(files)
+}
+
+/// Checks whether any of the .vm files declares the given function
+fn defines_function(program: &[VmFile], function_name: &str) -> bool {
+    program.iter().any(|file| {
+        file.commands
+            .iter()
+            .any(|command| matches!(command, Command::Function(name, _) if name == function_name))
+    })
+}
+
+pub fn create_output_path<P>(file: P) -> PathBuf
 where
     P: AsRef<Path> + std::fmt::Debug,
 {
     let mut input_file = PathBuf::from(file.as_ref());
 
-    let output_file = if input_file.is_dir() {
-        if input_file == PathBuf::from(".") {
+    if input_file.is_dir() {
+        if input_file == Path::new(".") {
             let mut current_dir = env::current_dir().expect("Should be in some directory");
             let parent_name = current_dir.file_name().unwrap().to_str().unwrap();
             current_dir.push(format!("{}.asm", parent_name));
@@ -102,9 +301,7 @@ where
     } else {
         input_file.set_extension("asm");
         input_file
-    };
-
-    output_file
+    }
 }
 
 #[cfg(test)]
@@ -125,26 +322,130 @@ mod tests {
             valid_files("../nand2tetris/nand2tetris/projects/8/ProgramFlow/BasicLoop/BasicLoop.vm");
         assert_eq!(
             single_file.unwrap(),
-            vec![Path::new(
-                "../nand2tetris/nand2tetris/projects/8/ProgramFlow/BasicLoop/BasicLoop.vm"
-            )
-            .to_path_buf()]
+            vec![
+                Path::new(
+                    "../nand2tetris/nand2tetris/projects/8/ProgramFlow/BasicLoop/BasicLoop.vm"
+                )
+                .to_path_buf()
+            ]
         );
 
         let multiple_files =
             valid_files("../nand2tetris/nand2tetris/projects/8/FunctionCalls/StaticsTest");
-        assert!(multiple_files.as_ref().unwrap().contains(
-            &Path::new("../nand2tetris/nand2tetris/projects/8/FunctionCalls/StaticsTest/Class1.vm")
+        assert!(
+            multiple_files.as_ref().unwrap().contains(
+                &Path::new(
+                    "../nand2tetris/nand2tetris/projects/8/FunctionCalls/StaticsTest/Class1.vm"
+                )
                 .to_path_buf()
-        ));
-        assert!(multiple_files.as_ref().unwrap().contains(
-            &Path::new("../nand2tetris/nand2tetris/projects/8/FunctionCalls/StaticsTest/Class2.vm")
+            )
+        );
+        assert!(
+            multiple_files.as_ref().unwrap().contains(
+                &Path::new(
+                    "../nand2tetris/nand2tetris/projects/8/FunctionCalls/StaticsTest/Class2.vm"
+                )
                 .to_path_buf()
-        ));
-        assert!(multiple_files.as_ref().unwrap().contains(
-            &Path::new("../nand2tetris/nand2tetris/projects/8/FunctionCalls/StaticsTest/Sys.vm")
+            )
+        );
+        assert!(
+            multiple_files.as_ref().unwrap().contains(
+                &Path::new(
+                    "../nand2tetris/nand2tetris/projects/8/FunctionCalls/StaticsTest/Sys.vm"
+                )
                 .to_path_buf()
-        ));
+            )
+        );
+    }
+
+    fn scratch_directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("n2ttools_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn test_files_are_sorted() {
+        let directory = scratch_directory("sorted");
+        for name in ["Sys.vm", "Main.vm", "Array.vm", "notes.txt"] {
+            File::create(directory.join(name)).unwrap();
+        }
+
+        assert_eq!(
+            valid_files(&directory).unwrap(),
+            vec![
+                directory.join("Array.vm"),
+                directory.join("Main.vm"),
+                directory.join("Sys.vm")
+            ]
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_manifest_order() {
+        let directory = scratch_directory("manifest");
+        for name in ["Sys.vm", "Main.vm"] {
+            File::create(directory.join(name)).unwrap();
+        }
+        let manifest = directory.join("build.txt");
+        fs::write(&manifest, "# boot first\nSys.vm\n\nMain.vm\n").unwrap();
+
+        assert_eq!(
+            manifest_files(&manifest).unwrap(),
+            vec![directory.join("Sys.vm"), directory.join("Main.vm")]
+        );
+
+        fs::write(&manifest, "Missing.vm\n").unwrap();
+        assert!(manifest_files(&manifest).is_none());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_translation_is_reproducible() {
+        let files = [
+            (
+                "Sys.vm",
+                "function Sys.init 0\ncall Main.main 0\nlabel END\ngoto END\n",
+            ),
+            ("Main.vm", "function Main.main 0\npush static 0\nreturn\n"),
+            ("Array.vm", "function Array.new 0\npush static 0\nreturn\n"),
+        ];
+        let options = VmOptions {
+            bootstrap: Bootstrap::default(),
+            code_gen: CodeGen::Inline,
+            strategy: Strategy::Stack,
+            compare_strategies: false,
+            optimize: false,
+            tail_calls: false,
+            inline: None,
+            manifest: None,
+            annotate: false,
+            source_map: false,
+            statics: false,
+            verify: false,
+        };
+
+        // the same files created in opposite orders
+        let mut outputs = vec![];
+        for (name, order) in [("forwards", [0, 1, 2]), ("backwards", [2, 1, 0])] {
+            let directory = scratch_directory(name);
+            for index in order {
+                let (file_name, contents) = files[index];
+                fs::write(directory.join(file_name), contents).unwrap();
+            }
+            run_vm(&directory, options.clone(), false);
+            outputs.push(fs::read_to_string(create_output_path(&directory)).unwrap());
+            fs::remove_dir_all(&directory).unwrap();
+        }
+
+        assert_eq!(outputs[0], outputs[1]);
+        // translated in name order, so Array's static comes before Main's
+        let position = |text: &str| outputs[0].find(text).unwrap();
+        assert!(position("(Array.new)") < position("(Main.main)"));
+        assert!(position("(Main.main)") < position("(Sys.init)"));
+        assert!(position("@Array.0") < position("@Main.0"));
     }
 
     #[test]
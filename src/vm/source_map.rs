@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+
+use crate::vm::commands::Command;
+use crate::vm::parser::Parser;
+
+/// Where the assembly for one vm command came from and where it ended up
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceEntry {
+    pub file: String,
+    /// Line of the command in the .vm file, counting from 1
+    pub line: usize,
+    pub command: String,
+    /// First .asm line written for the command, counting from 0
+    pub asm_start: usize,
+    /// One past the last .asm line written for the command
+    pub asm_end: usize,
+    /// First ROM address of the command, filled in by the assembler
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rom_start: Option<usize>,
+    /// One past the last ROM address of the command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rom_end: Option<usize>,
+}
+
+impl Parser {
+    /// Number of complete lines written to the output so far
+    pub fn output_line(&mut self) -> usize {
+        self.counted_lines += self.output[self.counted_bytes..].matches('\n').count();
+        self.counted_bytes = self.output.len();
+        self.counted_lines
+    }
+
+    /// Translates a command, noting it in the output and the source map when asked to
+    pub fn translate_mapped(&mut self, command: &Command, line: usize) {
+        if self.annotate {
+            self.output += &format!("// {}.vm:{}: {}\n", self.file_name, line, command);
+        }
+        if self.source_map.is_none() {
+            self.translate_command(command);
+            return;
+        }
+
+        let asm_start = self.output_line();
+        self.translate_command(command);
+        let asm_end = self.output_line();
+
+        let entry = SourceEntry {
+            file: format!("{}.vm", self.file_name),
+            line,
+            command: command.to_string(),
+            asm_start,
+            asm_end,
+            rom_start: None,
+            rom_end: None,
+        };
+        if let Some(source_map) = self.source_map.as_mut() {
+            source_map.push(entry);
+        }
+    }
+}
+
+/// The ROM address each line of assembly would be placed at, plus the address after the last line
+pub fn rom_addresses(asm: &str) -> Vec<usize> {
+    let mut addresses = vec![];
+    let mut address = 0;
+    for line in asm.lines().map(str::trim) {
+        addresses.push(address);
+        if !(line.is_empty() | line.starts_with("//") | line.starts_with("(")) {
+            address += 1;
+        }
+    }
+    addresses.push(address);
+    addresses
+}
+
+/// Fills in the ROM range of every entry from the assembly it points into
+pub fn add_rom_addresses(entries: &mut [SourceEntry], asm: &str) {
+    let addresses = rom_addresses(asm);
+    let address = |line: usize| addresses[line.min(addresses.len() - 1)];
+    for entry in entries {
+        entry.rom_start = Some(address(entry.asm_start));
+        entry.rom_end = Some(address(entry.asm_end));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::VmFile;
+
+    #[test]
+    fn test_source_map_ranges() {
+        let file = VmFile::new(
+            String::from("Main"),
+            ["push constant 7", "label LOOP", "pop temp 0"]
+                .iter()
+                .map(|line| line.parse().unwrap())
+                .collect(),
+        );
+        let mut parser = Parser::new(5);
+        parser.source_map = Some(vec![]);
+        parser.translate_file(&file, false);
+
+        let mut entries = parser.source_map.unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!((entries[0].line, entries[0].asm_start), (1, 0));
+        assert_eq!(entries[1].asm_start, entries[0].asm_end);
+        assert_eq!(entries[1].command, "label LOOP");
+
+        add_rom_addresses(&mut entries, &parser.output);
+        // the label takes up a line of assembly but no room in ROM
+        assert_eq!(entries[1].rom_start, entries[1].rom_end);
+        assert_eq!(entries[2].rom_start, entries[0].rom_end);
+        assert_eq!(
+            entries[2].rom_end,
+            Some(crate::vm::compact::instruction_count(&parser.output))
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::assembler::symbol_table::create_symbol_table;
+use crate::vm::parser::VmFile;
+
+/// First address the assembler hands out to variables
+pub const STATIC_START: usize = 16;
+/// Last address before the stack starts at 256
+pub const STATIC_END: usize = 255;
+
+/// How the assembler's variable region (RAM 16-255) gets used by a translated program
+#[derive(Debug, Default)]
+pub struct StaticReport {
+    /// Number of distinct static variables each .vm file uses
+    pub files: BTreeMap<String, usize>,
+    /// Variables the translator itself needs, like TEMP_FRAME
+    pub translator: Vec<String>,
+}
+
+impl StaticReport {
+    /// Works out every variable the assembler will allocate for the generated assembly
+    pub fn new(program: &[VmFile], asm: &str) -> Self {
+        let file_names: BTreeSet<&str> = program.iter().map(|file| file.name.as_str()).collect();
+
+        let mut report = StaticReport::default();
+        for file in program {
+            report.files.insert(file.name.clone(), 0);
+        }
+        for symbol in variable_symbols(asm) {
+            let static_of = symbol
+                .rsplit_once('.')
+                .filter(|(name, index)| file_names.contains(name) && index.parse::<u16>().is_ok());
+            match static_of {
+                Some((name, _)) => *report.files.entry(name.to_string()).or_default() += 1,
+                None => report.translator.push(symbol),
+            }
+        }
+        report
+    }
+
+    pub fn total(&self) -> usize {
+        self.files.values().sum::<usize>() + self.translator.len()
+    }
+
+    pub fn capacity() -> usize {
+        STATIC_END - STATIC_START + 1
+    }
+
+    pub fn fits(&self) -> bool {
+        self.total() <= StaticReport::capacity()
+    }
+
+    pub fn print(&self) {
+        println!(
+            "Variables use {} of the {} words in RAM {}-{}",
+            self.total(),
+            StaticReport::capacity(),
+            STATIC_START,
+            STATIC_END
+        );
+        for (name, count) in &self.files {
+            println!("    {:<20} {} statics", name, count);
+        }
+        if !self.translator.is_empty() {
+            println!(
+                "    {:<20} {} ({})",
+                "translator",
+                self.translator.len(),
+                self.translator.join(", ")
+            );
+        }
+    }
+}
+
+/// Symbols the assembler will turn into variables, in the order it allocates them:
+/// anything used in an A-instruction that isn't a number, a label or predefined
+pub fn variable_symbols(asm: &str) -> Vec<String> {
+    let predefined = create_symbol_table();
+    let labels: BTreeSet<&str> = asm
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| line.strip_prefix("(")?.strip_suffix(")"))
+        .collect();
+
+    let mut seen = BTreeSet::new();
+    let mut symbols = vec![];
+    for line in asm.lines().map(str::trim) {
+        let Some(symbol) = line.strip_prefix("@") else {
+            continue;
+        };
+        if symbol.starts_with(|c: char| c.is_ascii_digit())
+            || labels.contains(symbol)
+            || predefined.contains_key(symbol)
+        {
+            continue;
+        }
+        if seen.insert(symbol) {
+            symbols.push(symbol.to_string());
+        }
+    }
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser::Parser;
+
+    fn file(name: &str, lines: &[String]) -> VmFile {
+        VmFile::new(
+            String::from(name),
+            lines.iter().map(|line| line.parse().unwrap()).collect(),
+        )
+    }
+
+    #[test]
+    fn test_static_report() {
+        let program = vec![
+            file(
+                "Main",
+                &[
+                    String::from("function Main.main 0"),
+                    String::from("push static 0"),
+                    String::from("pop static 3"),
+                    String::from("push static 0"),
+                    String::from("return"),
+                ],
+            ),
+            file(
+                "Ball",
+                &(0..240)
+                    .map(|i| format!("push static {}", i))
+                    .collect::<Vec<_>>(),
+            ),
+        ];
+        let mut parser = Parser::new(5);
+        for vm_file in &program {
+            parser.translate_file(vm_file, false);
+        }
+
+        let report = StaticReport::new(&program, &parser.output);
+        assert_eq!(report.files["Main"], 2);
+        assert_eq!(report.files["Ball"], 240);
+        assert_eq!(report.translator, vec!["TEMP_FRAME", "TEMP_RET_ADDRESS"]);
+        assert!(!report.fits());
+    }
+}
@@ -0,0 +1,183 @@
+use crate::vm::parser::Parser;
+
+impl Parser {
+    /// Translates `call fn_name arg_count` followed by `return` by handing the current
+    /// frame over to the called function. Its arguments and the saved frame of our
+    /// caller get copied down to ARG, so the called function returns straight to
+    /// our caller and the stack doesn't grow with each call
+    pub fn handle_tail_call(&mut self, fn_name: &str, arg_count: u16) {
+        let block = arg_count as u32 + 5;
+
+        // push our caller's saved frame (return address, LCL, ARG, THIS, THAT)
+        // on top of the arguments
+        for offset in (1..=5).rev() {
+            self.output += "@LCL\n";
+            self.output += "D=M\n";
+            self.output += &format!("@{}\n", offset);
+            self.output += "A=D-A\n";
+            self.output += "D=M\n";
+            self.push_d();
+        }
+
+        // R13 = SP - block, R14 = ARG, R15 = block
+        self.output += &format!("@{}\n", block);
+        self.output += "D=A\n";
+        self.output += "@R15\n";
+        self.output += "M=D\n";
+        self.output += "@SP\n";
+        self.output += "D=M\n";
+        self.output += "@R15\n";
+        self.output += "D=D-M\n";
+        self.output += "@R13\n";
+        self.output += "M=D\n";
+        self.output += "@ARG\n";
+        self.output += "D=M\n";
+        self.output += "@R14\n";
+        self.output += "M=D\n";
+
+        // ARG is always below the block, so copying from the front never
+        // overwrites a word before it has been read
+        self.output += &format!("(TAIL_COPY_{})\n", self.general_return_number);
+        self.output += "@R13\n";
+        self.output += "A=M\n";
+        self.output += "D=M\n";
+        self.output += "@R14\n";
+        self.output += "A=M\n";
+        self.output += "M=D\n";
+        self.output += "@R13\n";
+        self.output += "M=M+1\n";
+        self.output += "@R14\n";
+        self.output += "M=M+1\n";
+        self.output += "@R15\n";
+        self.output += "MD=M-1\n";
+        self.output += &format!("@TAIL_COPY_{}\n", self.general_return_number);
+        self.output += "D;JGT\n";
+        self.general_return_number += 1;
+
+        // LCL = SP = ARG + block, just like after a regular call
+        self.output += "@ARG\n";
+        self.output += "D=M\n";
+        self.output += &format!("@{}\n", block);
+        self.output += "D=D+A\n";
+        self.output += "@LCL\n";
+        self.output += "M=D\n";
+        self.output += "@SP\n";
+        self.output += "M=D\n";
+
+        self.output += &format!("@{}\n", fn_name);
+        self.output += "0;JMP\n";
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::cpu::Cpu;
+    use crate::vm::bootstrap::Bootstrap;
+    use crate::vm::parser::{Parser, VmFile};
+
+    fn file(name: &str, lines: &[&str]) -> VmFile {
+        VmFile::new(
+            String::from(name),
+            lines.iter().map(|line| line.parse().unwrap()).collect(),
+        )
+    }
+
+    #[test]
+    fn test_tail_call_skips_return() {
+        let file = VmFile::new(
+            String::from("Main"),
+            [
+                "function Main.loop 0",
+                "push argument 0",
+                "call Main.loop 1",
+                "return",
+            ]
+            .iter()
+            .map(|line| line.parse().unwrap())
+            .collect(),
+        );
+        let mut parser = Parser::new(5);
+        parser.tail_calls = true;
+        parser.translate_file(&file, false);
+
+        assert!(parser.output.contains("(TAIL_COPY_0)"));
+        assert!(!parser.output.contains("Main.loop$ret"));
+        assert!(!parser.output.contains("TEMP_FRAME"));
+    }
+
+    #[test]
+    fn test_tail_calls_run_in_constant_stack() {
+        let program = [
+            file(
+                "Main",
+                &[
+                    // hands its one argument frame over to a call with two
+                    "function Main.outer 1",
+                    "push constant 4000",
+                    "pop pointer 0",
+                    "push argument 0",
+                    "push constant 0",
+                    "call Main.sum 2",
+                    "return",
+                    // sum(n, total) = sum(n - 1, total + n)
+                    "function Main.sum 0",
+                    "push argument 0",
+                    "push constant 0",
+                    "eq",
+                    "if-goto DONE",
+                    "push argument 0",
+                    "push constant 1",
+                    "sub",
+                    "push argument 1",
+                    "push argument 0",
+                    "add",
+                    "call Main.sum 2",
+                    "return",
+                    "label DONE",
+                    "push argument 1",
+                    "return",
+                ],
+            ),
+            file(
+                "Sys",
+                &[
+                    "function Sys.init 1",
+                    "push constant 77",
+                    "pop local 0",
+                    "push constant 3000",
+                    "pop pointer 0",
+                    "push constant 200",
+                    "call Main.outer 1",
+                    "pop temp 1",
+                    "push local 0",
+                    "pop temp 2",
+                    "push pointer 0",
+                    "pop temp 3",
+                    "label END",
+                    "goto END",
+                ],
+            ),
+        ];
+        let mut parser = Parser::new(5);
+        parser.tail_calls = true;
+        parser.bootstrap(&Bootstrap::default());
+        for file in &program {
+            parser.translate_file(file, false);
+        }
+
+        let mut cpu = Cpu::from_asm(&parser.output);
+        let mut max_sp = 0;
+        while !cpu.halted && cpu.steps < 1_000_000 {
+            cpu.step();
+            max_sp = max_sp.max(cpu.ram[0]);
+        }
+        assert!(cpu.halted);
+        // the sum, then Sys.init's local and THIS as it left them
+        assert_eq!(cpu.ram[6..9], [20100, 77, 3000]);
+        // back to Sys.init's frame: the bootstrap's saved frame and its local
+        assert_eq!(cpu.ram[0], 256 + 5 + 1);
+        assert_eq!(cpu.ram[1], 256 + 5);
+        // 200 regular calls would take over a thousand words of stack
+        assert!(max_sp < 256 + 40, "the stack grew to {}", max_sp);
+    }
+}
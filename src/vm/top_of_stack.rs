@@ -0,0 +1,245 @@
+use clap::ValueEnum;
+
+use crate::vm::commands::Command;
+use crate::vm::math::is_math;
+use crate::vm::parser::Parser;
+
+/// Where the top of the stack lives while translating
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Strategy {
+    /// Every value is written to RAM as soon as it is pushed
+    Stack,
+    /// The top of the stack is kept in D and only written to RAM when it has to be
+    Register,
+}
+
+/// Offsets up to this are reached with A=A+1 rather than adding the index
+const MAX_INCREMENTS: u16 = 6;
+
+impl Parser {
+    /// Translates the commands that can work on a top of stack held in D.
+    /// Everything else gets the stack written back to RAM first and
+    /// is left for the regular translation
+    pub fn translate_cached(&mut self, command: &Command) -> bool {
+        match command {
+            // hold on to constants pushed on top of D, since most of the time
+            // they can be folded straight into the next instruction
+            Command::Push(segment, index)
+                if segment == "constant" && self.top_in_d && self.pending_constant.is_none() =>
+            {
+                self.pending_constant = Some(*index);
+            }
+            Command::Push(segment, index) => {
+                self.spill();
+                self.load_segment(segment, *index);
+                self.top_in_d = true;
+            }
+            Command::Pop(segment, index) => {
+                self.fill();
+                self.store_segment(segment, *index);
+                self.top_in_d = false;
+            }
+            Command::Arithmetic(operator) if is_math(operator) => {
+                match (operator.as_str(), self.pending_constant) {
+                    ("shl", Some(constant)) => {
+                        self.pending_constant = None;
+                        self.constant_shift_left(constant);
+                    }
+                    // the shared routines work on the stack in RAM
+                    _ => {
+                        self.spill();
+                        return false;
+                    }
+                }
+            }
+            Command::Arithmetic(operator) => {
+                match self.pending_constant.take() {
+                    Some(constant) if operator != "neg" && operator != "not" => {
+                        self.constant_arithmetic(operator, constant);
+                    }
+                    pending => {
+                        self.pending_constant = pending;
+                        self.fill();
+                        self.cached_arithmetic(operator);
+                    }
+                }
+                self.top_in_d = true;
+            }
+            Command::If(label) => {
+                self.fill();
+                self.output += &format!("@{}${}\n", self.function_name, label);
+                self.output += "D;JNE\n";
+                self.top_in_d = false;
+            }
+            Command::IfNot(label) => {
+                self.fill();
+                self.output += &format!("@{}${}\n", self.function_name, label);
+                self.output += "D;JEQ\n";
+                self.top_in_d = false;
+            }
+            _ => {
+                self.spill();
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Turn a held constant into a regular top of stack in D
+    fn materialize(&mut self) {
+        if let Some(constant) = self.pending_constant.take() {
+            self.push_d();
+            self.output += &format!("@{}\n", constant);
+            self.output += "D=A\n";
+        }
+    }
+
+    /// Write the top of the stack held in D back to RAM
+    pub fn spill(&mut self) {
+        self.materialize();
+        if self.top_in_d {
+            self.push_d();
+            self.top_in_d = false;
+        }
+    }
+
+    /// Make sure the top of the stack is in D
+    fn fill(&mut self) {
+        self.materialize();
+        if !self.top_in_d {
+            self.pop_stack();
+            self.top_in_d = true;
+        }
+    }
+
+    /// Write D to segment[index]
+    fn store_segment(&mut self, segment: &str, index: u16) {
+        let base = match segment {
+            "local" => "LCL",
+            "argument" => "ARG",
+            "this" => "THIS",
+            "that" => "THAT",
+            "pointer" => {
+                self.output += if index == 0 { "@THIS\n" } else { "@THAT\n" };
+                self.output += "M=D\n";
+                return;
+            }
+            "static" => {
+                self.output += &format!("@{}.{}\n", self.file_name, index);
+                self.output += "M=D\n";
+                return;
+            }
+            "temp" => {
+                self.output += &format!("@{}\n", self.temp_base + index as u32);
+                self.output += "M=D\n";
+                return;
+            }
+            _ => panic!("Found an unknown segment: {}", segment),
+        };
+
+        if index <= MAX_INCREMENTS {
+            self.output += &format!("@{}\n", base);
+            self.output += "A=M\n";
+            for _ in 0..index {
+                self.output += "A=A+1\n";
+            }
+            self.output += "M=D\n";
+            return;
+        }
+
+        // D is in use, so park it while the address is worked out
+        self.output += "@R13\n";
+        self.output += "M=D\n";
+        self.output += &format!("@{}\n", base);
+        self.output += "D=M\n";
+        self.output += &format!("@{}\n", index);
+        self.output += "D=D+A\n";
+        self.output += "@R14\n";
+        self.output += "M=D\n";
+        self.output += "@R13\n";
+        self.output += "D=M\n";
+        self.output += "@R14\n";
+        self.output += "A=M\n";
+        self.output += "M=D\n";
+    }
+
+    /// D holds y, and x is still on the stack in RAM
+    fn cached_arithmetic(&mut self, operator: &str) {
+        match operator {
+            "neg" => self.output += "D=-D\n",
+            "not" => self.output += "D=!D\n",
+            "add" | "sub" | "and" | "or" | "eq" | "gt" | "lt" => {
+                // pop x, leaving A pointing at it
+                self.output += "@SP\n";
+                self.output += "AM=M-1\n";
+                match operator {
+                    "add" => self.output += "D=D+M\n",
+                    "sub" => self.output += "D=M-D\n",
+                    "and" => self.output += "D=D&M\n",
+                    "or" => self.output += "D=D|M\n",
+                    _ => {
+                        self.output += "D=M-D\n";
+                        self.cached_comparison(operator);
+                    }
+                }
+            }
+            _ => panic!("ran into a different arithmetic"),
+        }
+    }
+
+    /// D holds x and y is a constant that was never pushed
+    fn constant_arithmetic(&mut self, operator: &str, constant: u16) {
+        self.output += &format!("@{}\n", constant);
+        match operator {
+            "add" => self.output += "D=D+A\n",
+            "sub" => self.output += "D=D-A\n",
+            "and" => self.output += "D=D&A\n",
+            "or" => self.output += "D=D|A\n",
+            "eq" | "gt" | "lt" => {
+                self.output += "D=D-A\n";
+                self.cached_comparison(operator);
+            }
+            _ => panic!("ran into a different arithmetic"),
+        }
+    }
+
+    /// D holds x - y and gets replaced with the result of the comparison
+    fn cached_comparison(&mut self, operator: &str) {
+        let jump = match operator {
+            "eq" => "JEQ",
+            "gt" => "JGT",
+            _ => "JLT",
+        };
+
+        self.output += &format!("@TRUE_{}\n", self.general_return_number);
+        self.output += &format!("D;{}\n", jump);
+        self.output += "D=0\n";
+        self.output += &format!("@RETURN_ADDRESS_{}\n", self.general_return_number);
+        self.output += "0;JMP\n";
+        self.output += &format!("(TRUE_{})\n", self.general_return_number);
+        self.output += "D=-1\n";
+        self.output += &format!("(RETURN_ADDRESS_{})\n", self.general_return_number);
+
+        self.general_return_number += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::vm::compact::instruction_count;
+
+    #[test]
+    fn test_increment_local() {
+        let mut parser = Parser::new(5);
+        parser.strategy = Strategy::Register;
+        for line in ["push local 0", "push constant 1", "add", "pop local 0"] {
+            parser.translate_command(&Command::from_str(line).unwrap());
+        }
+
+        assert!(!parser.top_in_d);
+        assert_eq!(instruction_count(&parser.output), 11);
+    }
+}
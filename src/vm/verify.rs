@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::vm::commands::Command;
+use crate::vm::optimizer::function_ranges;
+use crate::vm::parser::VmFile;
+
+/// A place where a function's use of the stack doesn't add up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackError {
+    pub file: String,
+    pub line: usize,
+    pub function: String,
+    pub message: String,
+}
+
+impl Display for StackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.vm:{}: in {}: {}",
+            self.file, self.line, self.function, self.message
+        )
+    }
+}
+
+/// How many values a command needs on the stack, and how it changes the depth
+fn stack_effect(command: &Command) -> (i32, i32) {
+    match command {
+        Command::Push(..) => (0, 1),
+        Command::Pop(..) | Command::If(_) | Command::IfNot(_) => (1, -1),
+        Command::Arithmetic(operator) if operator == "neg" || operator == "not" => (1, 0),
+        Command::Arithmetic(_) => (2, -1),
+        Command::Call(_, args) => (*args as i32, 1 - *args as i32),
+        Command::Return => (1, -1),
+        Command::Label(_) | Command::Goto(_) | Command::Function(..) => (0, 0),
+    }
+}
+
+/// Checks every function in the program, following each branch to make sure
+/// the stack depth is the same wherever paths meet and that every `return`
+/// leaves exactly the return value on the function's stack
+pub fn verify(program: &[VmFile]) -> Vec<StackError> {
+    let mut errors = vec![];
+    for file in program {
+        for (start, end) in function_ranges(&file.commands) {
+            if let Some(Command::Function(name, _)) = file.commands.get(start) {
+                verify_function(file, name, start, end, &mut errors);
+            }
+        }
+    }
+    errors
+}
+
+fn verify_function(
+    file: &VmFile,
+    function_name: &str,
+    start: usize,
+    end: usize,
+    errors: &mut Vec<StackError>,
+) {
+    let body = &file.commands[start..end];
+    let error = |i: usize, message: String| StackError {
+        file: file.name.clone(),
+        line: file.lines[start + i],
+        function: function_name.to_string(),
+        message,
+    };
+
+    let labels: BTreeMap<&String, usize> = body
+        .iter()
+        .enumerate()
+        .filter_map(|(i, command)| match command {
+            Command::Label(label) => Some((label, i)),
+            _ => None,
+        })
+        .collect();
+
+    // stack depth before each command, relative to the start of the function
+    let mut depths: Vec<Option<i32>> = vec![None; body.len()];
+    let mut pending = vec![(0, 0)];
+    while let Some((i, depth)) = pending.pop() {
+        if i >= body.len() {
+            errors.push(error(
+                body.len() - 1,
+                String::from("runs off the end of the function without returning"),
+            ));
+            continue;
+        }
+        match depths[i] {
+            Some(seen) if seen == depth => continue,
+            Some(seen) => {
+                errors.push(error(
+                    i,
+                    format!("paths meet here with stack depths {} and {}", seen, depth),
+                ));
+                continue;
+            }
+            None => depths[i] = Some(depth),
+        }
+
+        let command = &body[i];
+        let (needs, change) = stack_effect(command);
+        if depth < needs {
+            errors.push(error(
+                i,
+                format!(
+                    "'{}' needs {} value(s) but the stack only has {}",
+                    command, needs, depth
+                ),
+            ));
+            continue;
+        }
+        let after = depth + change;
+
+        match command {
+            Command::Return => {
+                if depth != 1 {
+                    errors.push(error(
+                        i,
+                        format!("returns with {} values on the stack instead of 1", depth),
+                    ));
+                }
+            }
+            Command::Goto(label) | Command::If(label) | Command::IfNot(label) => {
+                match labels.get(label) {
+                    Some(target) => pending.push((*target, after)),
+                    None => errors.push(error(i, format!("jumps to unknown label '{}'", label))),
+                }
+                if !matches!(command, Command::Goto(_)) {
+                    pending.push((i + 1, after));
+                }
+            }
+            _ => pending.push((i + 1, after)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(lines: &[&str]) -> Vec<String> {
+        let program = vec![VmFile::new(
+            String::from("Main"),
+            lines.iter().map(|line| line.parse().unwrap()).collect(),
+        )];
+        verify(&program)
+            .iter()
+            .map(|error| error.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_verify() {
+        assert!(
+            messages(&[
+                "function Main.abs 0",
+                "push argument 0",
+                "push constant 0",
+                "lt",
+                "if-goto NEGATIVE",
+                "push argument 0",
+                "return",
+                "label NEGATIVE",
+                "push argument 0",
+                "neg",
+                "return",
+            ])
+            .is_empty()
+        );
+
+        assert_eq!(
+            messages(&["function Main.f 0", "if-goto END", "label END", "return"]),
+            vec!["Main.vm:2: in Main.f: 'if-goto END' needs 1 value(s) but the stack only has 0"]
+        );
+
+        assert_eq!(
+            messages(&[
+                "function Main.g 0",
+                "push constant 1",
+                "if-goto SKIP",
+                "push constant 2",
+                "label SKIP",
+                "push constant 3",
+                "return",
+            ]),
+            vec![
+                "Main.vm:7: in Main.g: returns with 2 values on the stack instead of 1",
+                "Main.vm:5: in Main.g: paths meet here with stack depths 1 and 0"
+            ]
+        );
+
+        assert_eq!(
+            messages(&[
+                "function Main.h 0",
+                "push constant 1",
+                "push constant 2",
+                "return"
+            ]),
+            vec!["Main.vm:4: in Main.h: returns with 2 values on the stack instead of 1"]
+        );
+    }
+}